@@ -5,3 +5,16 @@ welcome = Welcome to COSMIC! ✨
 page-id = Page { $num }
 git-description = Git commit {$hash} on {$date}
 example-row = Example Row
+numbers-mode = Numbers only
+dim-minimized = Dim minimized windows
+refresh-applications = Refresh applications
+unknown-window = Unknown window
+activate = Activate
+minimize = Minimize
+restore = Restore
+close = Close
+move-to-workspace = Move to workspace
+window-closed = Window closed
+new-window = New window
+unknown-application = Unknown application
+icon-label = { $app }: { $title }
@@ -1,159 +1,1196 @@
 // SPDX-License-Identifier: MPL-2.0
 
-use crate::config::Config;
-use crate::icons::Icons;
-use crate::wayland_subscription::{self, AppToplevel, AppWorkspace, WaylandEvent};
+use crate::config::{ActiveEmphasis, ClickAction, Config, IconMode, Layout};
+use crate::desktop_matcher::DesktopMatcher;
+use crate::desktop_watcher_subscription;
+use crate::fl;
+use crate::icons::{Icons, ResolvedIcon};
+use crate::kinetic_scroll::KineticScroll;
+use crate::signal_subscription;
+use crate::wayland_subscription::{
+    self, AppToplevel, AppWorkspace, WaylandEvent, WaylandRequest, WaylandSender,
+};
+use crate::workspace_widget::{self, WorkspaceStyle, WorkspaceWidget};
 use cosmic::applet::Size;
+use cosmic::cctk::wayland_client::Proxy;
+use cosmic::cctk::wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1;
 use cosmic::cosmic_config::{self, CosmicConfigEntry};
-use cosmic::iced::{Limits, Subscription};
+use cosmic::iced::keyboard::Modifiers;
+use cosmic::iced::mouse::ScrollDelta;
+use cosmic::iced::platform_specific::shell::commands::popup::{destroy_popup, get_popup};
+use cosmic::iced::widget::scrollable::{AbsoluteOffset, Direction, Scrollbar, Viewport};
+use cosmic::iced::window::Id;
+use cosmic::iced::{Length, Limits, Subscription};
 use cosmic::prelude::*;
 use cosmic::widget;
-use std::collections::HashMap;
-use std::sync::LazyLock;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
 use wayland_protocols::ext::workspace::v1::client::ext_workspace_handle_v1::ExtWorkspaceHandleV1;
 
+/// Opacity of the overlay drawn on top of minimized window icons.
+const MINIMIZED_DIM_ALPHA: f32 = 0.6;
+
+/// Opacity of the overlay drawn on top of the icon of a window being dragged.
+const DRAGGED_DIM_ALPHA: f32 = 0.4;
+
+/// Number of window icons shown per workspace with `stacked_icons`, the rest are counted.
+const STACKED_ICONS_MAX: usize = 3;
+/// Fraction of an icon covered by the next one with `stacked_icons`.
+const STACKED_ICONS_OVERLAP: f32 = 0.4;
+
+/// Number of queued icons resolved per tick, at startup or after the icon cache was
+/// invalidated.
+const ICONS_PER_TICK: usize = 4;
+/// Interval between queued icon resolution ticks.
+const ICON_TICK_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Interval between frames of the pager's inertial scrolling.
+const SCROLL_TICK_INTERVAL: Duration = Duration::from_millis(16);
+/// Length of the pulse of a new window's icon, see [`Config::animate_new_windows`].
+const PULSE_DURATION: Duration = Duration::from_millis(600);
+/// Interval between frames of the new window pulse.
+const PULSE_TICK_INTERVAL: Duration = Duration::from_millis(33);
+/// Opacity of the pulse background at its start, it fades out from there.
+const PULSE_ALPHA: f32 = 0.5;
+
+/// Accumulated pixel delta of a smooth scroll that switches to the next workspace.
+const SWITCH_SCROLL_PIXELS: f32 = 50.0;
+
+/// Command toggling the COSMIC workspaces overview. There is no Wayland protocol for the
+/// overview, the command forwards to the running instance over DBus or starts one.
+const COSMIC_WORKSPACES: &str = "cosmic-workspaces";
+
+/// How long a workspace switch is shown before the compositor has to confirm it.
+const ACTIVATION_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Terminal running `Terminal=true` desktop entries unless `$TERMINAL` is set.
+const DEFAULT_TERMINAL: &str = "cosmic-term";
+
+/// Icon of windows that report no app id.
+const UNIDENTIFIED_ICON: &str = "application-x-executable";
+
 static AUTOSIZE_MAIN_ID: LazyLock<widget::Id> = LazyLock::new(|| widget::Id::new("autosize-main"));
+static PAGER_SCROLL_ID: LazyLock<widget::Id> = LazyLock::new(|| widget::Id::new("pager-scroll"));
 
 pub struct AppModel {
     /// Application state which is managed by the COSMIC runtime.
     core: cosmic::Core,
     /// Configuration data that persists between application runs.
     config: Config,
+    /// Handle for writing configuration changes made from the settings popup
+    config_handler: Option<cosmic_config::Config>,
+    /// The currently open popup, if any
+    popup: Option<(Id, PopupKind)>,
     /// Current workspaces
     workspaces: Vec<AppWorkspace>,
-    /// Current applications
+    /// Current applications, as reported by the Wayland thread. Shared with it until changed
+    /// here, e.g. by a focus change.
+    raw_toplevels:
+        Arc<HashMap<ExtWorkspaceHandleV1, HashMap<ExtForeignToplevelHandleV1, AppToplevel>>>,
+    /// Current applications per workspace, filtered and sorted for display
     workspace_toplevels: HashMap<ExtWorkspaceHandleV1, Vec<AppToplevel>>,
     /// App icon cache
     app_icons: Icons,
+    /// Channel for requests to the Wayland thread, set once it is connected
+    wayland_sender: Option<WaylandSender>,
+    /// Inertia of the scrollable pager, see [`Config::smooth_scroll`]
+    pager_scroll: KineticScroll,
+    /// Pixel scroll accumulated towards the next workspace switch
+    switch_scroll: f32,
+    /// Keyboard modifiers currently held, Shift pans the scrollable pager
+    modifiers: Modifiers,
+    /// Windows the user has seen, the rest are marked as new. Unset until the first toplevel
+    /// update, so windows open at startup don't count as new.
+    seen_toplevels: Option<HashSet<ExtForeignToplevelHandleV1>>,
+    /// Highest window sequence number received, unset until the first toplevel update
+    last_sequence: Option<u64>,
+    /// Integer scale of the panel's output, icons are looked up at this resolution
+    output_scale: u16,
+    /// Start of the pulse of windows that just opened, see [`Config::animate_new_windows`]
+    pulses: HashMap<ExtForeignToplevelHandleV1, Instant>,
+    /// Keyboard selection in the open popup, an index into [`AppModel::popup_items`]
+    popup_selection: Option<usize>,
+    /// Window whose menu was opened last
+    menu_toplevel: Option<ExtForeignToplevelHandleV1>,
+    #[cfg(feature = "dbus-service")]
+    state_publisher: Option<crate::dbus_service::StatePublisher>,
+    /// Workspace shown as active before the compositor confirmed it, with the workspaces as
+    /// last reported to restore if it never does
+    pending_activation: Option<(ExtWorkspaceHandleV1, Vec<AppWorkspace>)>,
+    /// Window whose icon is being dragged onto another workspace
+    dragging: Option<ExtForeignToplevelHandleV1>,
+    /// Workspace the drag started on, releasing over it again is a click on the icon
+    drag_origin: Option<ExtWorkspaceHandleV1>,
+    /// Workspace under the pointer, the drop target while dragging
+    drop_target: Option<ExtWorkspaceHandleV1>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
     UpdateConfig(Config),
     WaylandEvent(WaylandEvent),
+    ReloadAll,
+    ActivateWorkspace(ExtWorkspaceHandleV1),
+    /// The compositor didn't confirm the activation of the workspace in time
+    ActivationTimedOut(ExtWorkspaceHandleV1),
+    IconResolved(String, ResolvedIcon),
+    ResolveQueuedIcons,
+    DesktopFilesChanged(Vec<PathBuf>),
+    RefreshDesktopEntries,
+    DesktopEntriesLoaded(Arc<DesktopMatcher>),
+    ToggleSettingsPopup,
+    PanelClicked,
+    /// Toggles the COSMIC workspaces overview, or the overview popup if it is unavailable
+    OpenOverview,
+    #[cfg(feature = "dbus-service")]
+    DbusServiceReady(crate::dbus_service::StatePublisher),
+    TogglePopup,
+    ToggleWindowList,
+    ToggleOverflowPopup,
+    PopupClosed(Id),
+    PopupKey(PopupKey),
+    ActivateToplevel(ExtForeignToplevelHandleV1),
+    SetNumbersMode(bool),
+    SetDimMinimized(bool),
+    PagerScrolled(Viewport),
+    PagerWheel(ScrollDelta),
+    PagerTick,
+    /// Redraws running new window pulses and ends finished ones
+    PulseTick,
+    ModifiersChanged(Modifiers),
+    AcknowledgeNewWindows,
+    IconThemeChanged(String),
+    DragToplevel(ExtForeignToplevelHandleV1),
+    /// Minimizes a window, or restores it if it is minimized
+    ToggleMinimize(ExtForeignToplevelHandleV1),
+    OpenWindowMenu(ExtForeignToplevelHandleV1),
+    CloseToplevel(ExtForeignToplevelHandleV1),
+    /// Starts a new instance of the app through its desktop entry
+    LaunchApp(String),
+    MoveToplevel(ExtForeignToplevelHandleV1, ExtWorkspaceHandleV1),
+    DragEnter(ExtWorkspaceHandleV1),
+    DragLeave(ExtWorkspaceHandleV1),
+    DropOnWorkspace(ExtWorkspaceHandleV1),
+    DragCancel,
+}
+
+/// The kinds of popup the applet can open from the panel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PopupKind {
+    Settings,
+    /// Every workspace as a section with its windows, opened from the panel button
+    Overview,
+    /// Classic window list with one row per window
+    WindowList,
+    /// Workspaces that didn't fit in the panel
+    Overflow,
+    /// Actions for the window in [`AppModel::menu_toplevel`], opened with a right click
+    WindowMenu,
+}
+
+/// A row of the open popup that can be selected with the keyboard.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum PopupItem {
+    Workspace(ExtWorkspaceHandleV1),
+    Toplevel(ExtForeignToplevelHandleV1),
+}
+
+/// Keys handled while a popup is open.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PopupKey {
+    Up,
+    Down,
+    Activate,
+    Close,
+}
+
+/// How occupied a workspace is, used to color the compact numeric pager.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Occupancy {
+    Empty,
+    Occupied,
+    Active,
+}
+
+impl Occupancy {
+    fn color(self, theme: &Theme) -> cosmic::iced::Color {
+        let cosmic = theme.cosmic();
+        match self {
+            Occupancy::Empty => cosmic::iced::Color {
+                a: 0.35,
+                ..cosmic.on_bg_color().into()
+            },
+            Occupancy::Occupied => cosmic.on_bg_color().into(),
+            Occupancy::Active => cosmic.accent_color().into(),
+        }
+    }
 }
 
 impl AppModel {
-    fn get_workspace_toplevels(&self, workspace: &AppWorkspace) -> Vec<AppToplevel> {
-        let res = self.workspace_toplevels.get(&workspace.handle);
-        if let Some(res) = res {
-            res.clone()
+    pub fn load_config() -> (Option<cosmic_config::Config>, Config) {
+        let handler =
+            cosmic_config::Config::new(<Self as cosmic::Application>::APP_ID, Config::VERSION).ok();
+        let mut config = handler
+            .as_ref()
+            .map(|context| match Config::get_entry(context) {
+                Ok(config) => config,
+                Err((_errors, config)) => {
+                    // for why in errors {
+                    //     tracing::error!(%why, "error loading app config");
+                    // }
+
+                    config
+                }
+            })
+            .unwrap_or_default();
+        config.validate();
+        config.apply_preset();
+        (handler, config)
+    }
+
+    /// Name shown for a window's application, the configured label if any, else the desktop
+    /// entry name if known, else the app id. Windows without an app id go by their title.
+    fn display_name(&self, toplevel: &AppToplevel) -> String {
+        if toplevel.app_id.is_empty() {
+            return if toplevel.title.is_empty() {
+                fl!("unknown-window")
+            } else {
+                toplevel.title.clone()
+            };
+        }
+        if let Some(label) = self.config.app_id_labels.get(&toplevel.app_id) {
+            return label.clone();
+        }
+        self.app_icons
+            .app_name(&toplevel.app_id)
+            .unwrap_or_else(|| toplevel.app_id.clone())
+    }
+
+    /// Name of a window's icon for tooltips and assistive tech, the application and the window
+    /// title. Apps showing the fallback icon go by "unknown application" unless they have a
+    /// configured label.
+    fn icon_label(&self, toplevel: &AppToplevel) -> String {
+        let unknown = toplevel.app_id.is_empty()
+            || (self.app_icons.is_fallback(&toplevel.app_id)
+                && !self.config.app_id_labels.contains_key(&toplevel.app_id));
+        let app = if unknown {
+            fl!("unknown-application")
+        } else {
+            self.display_name(toplevel)
+        };
+        if toplevel.title.is_empty() {
+            app
+        } else {
+            fl!("icon-label", app = app, title = toplevel.title.as_str())
+        }
+    }
+
+    /// The window's app icon, a generic one for windows without an app id
+    fn toplevel_icon(&self, toplevel: &AppToplevel) -> widget::icon::Icon {
+        if toplevel.app_id.is_empty() {
+            widget::icon::from_name(UNIDENTIFIED_ICON).icon()
         } else {
-            Vec::new()
+            self.app_icons.get_icon(&toplevel.app_id)
+        }
+    }
+
+    /// Resolves the icons referenced by the configuration, queueing every shown icon for
+    /// re-resolution if any icon lookup setting changed
+    fn load_config_icons(&mut self) {
+        self.app_icons
+            .set_cache_capacity(self.config.icon_cache_capacity);
+        // Not short-circuiting, every setter has to run
+        if self.app_icons.set_theme_fallbacks(&self.config.icon_theme_fallbacks)
+            | self.app_icons.set_overrides(&self.config.icon_overrides)
+            | self.app_icons.set_prefer_symbolic(self.config.prefer_symbolic_icons)
+            | self.app_icons.set_extra_dirs(&self.config.extra_icon_dirs)
+            | self.app_icons.set_lookup_size(self.icon_size(), self.output_scale)
+            | self.app_icons.set_recolor(self.config.recolor_icons_to_theme)
+            // Last, so it is looked up with the current settings
+            | self.app_icons.set_fallback_icon(self.config.fallback_icon.as_deref())
+        {
+            let app_ids = self.visible_app_ids();
+            self.app_icons.invalidate_all(app_ids);
         }
+        self.app_icons
+            .set_named_icons(self.config.workspace_icons.values());
+    }
+
+    /// Derives the displayed windows per workspace from the reported ones.
+    fn rebuild_workspace_toplevels(&mut self) {
+        self.workspace_toplevels = self.config.arrange_toplevels(&self.raw_toplevels);
+    }
+
+    /// The focused window, on any workspace
+    fn active_toplevel(&self) -> Option<&AppToplevel> {
+        self.workspace_toplevels
+            .values()
+            .flatten()
+            .find(|toplevel| toplevel.is_active)
     }
 
-    fn new_workspace_button(&self, workspace: &AppWorkspace) -> Element<'_, Message> {
+    /// Every shown window of every workspace once, sorted as within a workspace
+    fn flat_toplevels(&self) -> Vec<AppToplevel> {
+        let mut seen = HashSet::new();
+        let mut toplevels: Vec<AppToplevel> = self
+            .workspaces
+            .iter()
+            .flat_map(|workspace| self.get_workspace_toplevels(workspace))
+            // Sticky windows are listed on each of their workspaces
+            .filter(|toplevel| seen.insert(toplevel.handle.clone()))
+            .collect();
+        self.config.sort_toplevels(&mut toplevels);
+        toplevels
+    }
+
+    /// Shows `handle` as the active workspace of its group before the compositor reports it,
+    /// so switching feels instant. Reverts after [`ACTIVATION_TIMEOUT`] unless confirmed.
+    fn activate_optimistically(
+        &mut self,
+        handle: ExtWorkspaceHandleV1,
+    ) -> Task<cosmic::Action<Message>> {
+        let Some(group) = self
+            .workspaces
+            .iter()
+            .find(|workspace| workspace.handle == handle)
+            .map(|workspace| workspace.group.clone())
+        else {
+            return Task::none();
+        };
+        let confirmed = match self.pending_activation.take() {
+            Some((_, confirmed)) => confirmed,
+            None => self.workspaces.clone(),
+        };
+        for workspace in &mut self.workspaces {
+            if workspace.group == group {
+                workspace.is_active = workspace.handle == handle;
+            }
+        }
+        self.pending_activation = Some((handle.clone(), confirmed));
+        Task::perform(tokio::time::sleep(ACTIVATION_TIMEOUT), move |()| {
+            cosmic::Action::App(Message::ActivationTimedOut(handle))
+        })
+    }
+
+    /// Hands the rendered state to the DBus service, if it is enabled and running.
+    fn publish_state(&self) {
+        #[cfg(feature = "dbus-service")]
+        if let Some(publisher) = &self.state_publisher {
+            let toplevels = &self.workspace_toplevels;
+            publisher.publish(crate::snapshot::Snapshot::new(&self.workspaces, toplevels));
+        }
+    }
+
+    /// Starts the pulse of windows that appeared since the last toplevel update, windows
+    /// open at startup don't pulse.
+    fn start_pulses(&mut self) {
+        let max_sequence = self
+            .raw_toplevels
+            .values()
+            .flat_map(|toplevels| toplevels.values())
+            .map(|toplevel| toplevel.sequence)
+            .max();
+        let previous = self.last_sequence;
+        // Sequence numbers only grow, closing the newest window doesn't lower the mark
+        self.last_sequence = previous.max(max_sequence).or(Some(0));
+        let Some(previous) = previous else {
+            return;
+        };
+        if !self.config.animate_new_windows {
+            return;
+        }
+        let now = Instant::now();
+        for toplevel in self
+            .raw_toplevels
+            .values()
+            .flat_map(|toplevels| toplevels.values())
+        {
+            if toplevel.sequence > previous {
+                self.pulses.insert(toplevel.handle.clone(), now);
+            }
+        }
+    }
+
+    /// Opacity of the pulse of a window's icon, unset once it finished
+    fn pulse_alpha(&self, toplevel: &AppToplevel) -> Option<f32> {
+        let elapsed = self.pulses.get(&toplevel.handle)?.elapsed();
+        let progress = elapsed.as_secs_f32() / PULSE_DURATION.as_secs_f32();
+        (progress < 1.0).then(|| PULSE_ALPHA * (1.0 - progress))
+    }
+
+    /// Marks every current window as seen.
+    fn acknowledge_new_windows(&mut self) {
+        self.seen_toplevels = Some(
+            self.workspace_toplevels
+                .values()
+                .flatten()
+                .map(|toplevel| toplevel.handle.clone())
+                .collect(),
+        );
+    }
+
+    fn is_new_window(&self, toplevel: &AppToplevel) -> bool {
+        self.config.mark_new_windows
+            && self
+                .seen_toplevels
+                .as_ref()
+                .is_some_and(|seen| !seen.contains(&toplevel.handle))
+    }
+
+    /// Forgets the dragged window and the workspace under the pointer.
+    fn cancel_drag(&mut self) {
+        self.dragging = None;
+        self.drag_origin = None;
+        self.drop_target = None;
+    }
+
+    /// Opens a popup of the given kind, or closes the open popup if it is of that kind.
+    fn toggle_popup(&mut self, kind: PopupKind) -> Task<cosmic::Action<Message>> {
+        self.acknowledge_new_windows();
+        // The popup takes the pointer, the release ending a drag never reaches the panel
+        self.cancel_drag();
+        self.popup_selection = None;
+        let mut tasks = Vec::new();
+        if let Some((id, open_kind)) = self.popup.take() {
+            tasks.push(destroy_popup(id));
+            if open_kind == kind {
+                return Task::batch(tasks);
+            }
+        }
+        let Some(parent) = self.core.main_window_id() else {
+            return Task::batch(tasks);
+        };
+        let new_id = Id::unique();
+        self.popup = Some((new_id, kind));
+        let mut popup_settings = self
+            .core
+            .applet
+            .get_popup_settings(parent, new_id, None, None, None);
+        popup_settings.positioner.size_limits = Limits::NONE
+            .max_width(372.0)
+            .min_width(300.0)
+            .min_height(100.0)
+            .max_height(1080.0);
+        tasks.push(get_popup(popup_settings));
+        Task::batch(tasks)
+    }
+
+    /// Closes the window menu after one of its actions, other popups stay open
+    fn close_window_menu(&mut self) -> Task<cosmic::Action<Message>> {
+        match self.popup {
+            Some((id, PopupKind::WindowMenu)) => {
+                self.popup = None;
+                destroy_popup(id)
+            }
+            _ => Task::none(),
+        }
+    }
+
+    /// Whether a workspace gets the active styling, see [`ActiveEmphasis`].
+    fn is_emphasized(&self, workspace: &AppWorkspace) -> bool {
+        if !workspace.is_active {
+            return false;
+        }
+        match self.config.active_emphasis {
+            ActiveEmphasis::All => true,
+            ActiveEmphasis::PanelOutput => workspace.on_panel_output,
+            ActiveEmphasis::Focused => {
+                let has_focus = |handle: &ExtWorkspaceHandleV1| {
+                    self.workspace_toplevels
+                        .get(handle)
+                        .is_some_and(|toplevels| toplevels.iter().any(|tl| tl.is_active))
+                };
+                // Without a focused window anywhere, fall back to all active workspaces
+                has_focus(&workspace.handle) || !self.workspace_toplevels.keys().any(has_focus)
+            }
+        }
+    }
+
+    /// Application icon size, the configured override or the panel's suggested size
+    fn icon_size(&self) -> u16 {
         // Use the applet context to get proper sizing based on panel configuration
-        let icon_size = self.core.applet.suggested_size(true).0;
-        let text_size = match &self.core.applet.size {
+        self.config
+            .icon_size_override
+            .unwrap_or_else(|| self.core.applet.suggested_size(true).0)
+    }
+
+    /// Size of the applet's text in the panel, scaled with the panel size unless overridden
+    fn text_size(&self) -> u16 {
+        if let Some(size) = self.config.text_size_override {
+            return size;
+        }
+        let size = match &self.core.applet.size {
             Size::PanelSize(panel_size) => {
                 let size = panel_size.get_applet_icon_size_with_padding(false);
-                // Scale text with panel size
-                (size as f32 * 0.4).max(10.0) as u16
+                (size as f32 * 0.4).max(10.0)
             }
-            Size::Hardcoded((_w, _h)) => 14,
+            Size::Hardcoded(_) => 14.0,
         };
+        (size * self.config.text_size_scale).round() as u16
+    }
 
-        let spacing = self.core.applet.spacing as f32;
-        let icon_spacing = self.core.applet.spacing as f32 * 0.5;
-        let (padding_major, padding_minor) = self.core.applet.suggested_padding(true);
-        let padding = if self.core.applet.is_horizontal() {
-            [padding_minor as f32, padding_major as f32]
-        } else {
-            [padding_major as f32, padding_minor as f32]
+    /// Workspaces for the panel in the order they are shown, with their position among all
+    /// workspaces, without the empty ones if they are hidden. Active and pinned workspaces are
+    /// always included.
+    fn panel_workspaces(&self) -> Vec<(usize, &AppWorkspace)> {
+        let mut workspaces: Vec<_> = self
+            .workspaces
+            .iter()
+            .enumerate()
+            .filter(|(index, workspace)| {
+                !self.config.hide_empty_workspaces
+                    || workspace.is_active
+                    || self.config.is_workspace_pinned(&workspace.name, *index)
+                    || !self.get_workspace_toplevels(workspace).is_empty()
+            })
+            .collect();
+        if self.config.reverse_workspace_order {
+            workspaces.reverse();
+        }
+        workspaces
+    }
+
+    /// Number of leading panel workspaces rendered in the panel, the rest go behind the
+    /// overflow button.
+    fn visible_workspace_count(&self) -> usize {
+        let count = self.panel_workspaces().len();
+        match self.config.overflow_threshold {
+            Some(threshold) if count > threshold => threshold,
+            _ => count,
+        }
+    }
+
+    /// Activates the workspace `step` positions away from the active one.
+    fn switch_workspace(&mut self, step: isize) -> Task<cosmic::Action<Message>> {
+        let Some(current) = self
+            .workspaces
+            .iter()
+            .position(|ws| ws.is_active && ws.on_panel_output)
+            .or_else(|| self.workspaces.iter().position(|ws| ws.is_active))
+        else {
+            return Task::none();
         };
+        let target = current
+            .saturating_add_signed(step)
+            .min(self.workspaces.len() - 1);
+        if target != current {
+            let handle = self.workspaces[target].handle.clone();
+            let message = Message::ActivateWorkspace(handle);
+            return <Self as cosmic::Application>::update(self, message);
+        }
+        Task::none()
+    }
 
-        let mut content = widget::row()
-            .spacing(icon_spacing)
-            .align_y(cosmic::iced::Alignment::Center);
+    /// Starts `program` without waiting for it to exit, so only a failure to start is
+    /// reported. The child is reaped on a thread of its own.
+    fn spawn_detached(program: &str, args: &[String]) -> std::io::Result<()> {
+        let mut child = std::process::Command::new(program).args(args).spawn()?;
+        std::thread::spawn(move || {
+            let _ = child.wait();
+        });
+        Ok(())
+    }
 
-        let text = widget::text(format!("{}", workspace.name)).size(text_size);
+    /// Rows of the open popup in display order. Overview headings of workspaces without
+    /// windows are left out.
+    fn popup_items(&self) -> Vec<PopupItem> {
+        let windows = |with_headings: bool| -> Vec<PopupItem> {
+            let mut items = Vec::new();
+            for workspace in &self.workspaces {
+                let toplevels = self.workspace_toplevels.get(&workspace.handle);
+                let toplevels = toplevels.map(Vec::as_slice).unwrap_or_default();
+                if with_headings && !toplevels.is_empty() {
+                    items.push(PopupItem::Workspace(workspace.handle.clone()));
+                }
+                items.extend(
+                    toplevels
+                        .iter()
+                        .map(|toplevel| PopupItem::Toplevel(toplevel.handle.clone())),
+                );
+            }
+            items
+        };
+        match self.popup {
+            Some((_, PopupKind::Overview)) => windows(true),
+            Some((_, PopupKind::WindowList)) => windows(false),
+            Some((_, PopupKind::Overflow)) => self
+                .panel_workspaces()
+                .into_iter()
+                .skip(self.visible_workspace_count())
+                .map(|(_, workspace)| PopupItem::Workspace(workspace.handle.clone()))
+                .collect(),
+            Some((_, PopupKind::Settings | PopupKind::WindowMenu)) | None => Vec::new(),
+        }
+    }
 
-        let text = if workspace.is_active {
-            text.font(cosmic::iced::Font {
+    /// The popup row selected with the keyboard, looked up once per view.
+    fn selected_item(&self) -> Option<PopupItem> {
+        self.popup_selection
+            .and_then(|index| self.popup_items().into_iter().nth(index))
+    }
+
+    /// Draws the keyboard selection behind `row` if it shows `item`.
+    fn selectable<'a>(
+        row: Element<'a, Message>,
+        item: &PopupItem,
+        selected: Option<&PopupItem>,
+    ) -> Element<'a, Message> {
+        if selected != Some(item) {
+            return row;
+        }
+        widget::container(row)
+            .style(|theme: &Theme| {
+                let cosmic = theme.cosmic();
+                widget::container::Style {
+                    background: Some(
+                        cosmic::iced::Color::from(cosmic.background.component.hover).into(),
+                    ),
+                    border: cosmic::iced_core::Border {
+                        radius: cosmic.radius_s().into(),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }
+            })
+            .into()
+    }
+
+    /// One clickable row per workspace that didn't fit in the panel, with its window icons.
+    fn overflow_popup_content(&self) -> Element<'_, Message> {
+        let icon_size = 24;
+        let selected = self.selected_item();
+        let mut list = widget::column().spacing(2);
+        let workspaces = self.panel_workspaces();
+        for (_, workspace) in workspaces.into_iter().skip(self.visible_workspace_count()) {
+            let name = widget::text(workspace.name.clone());
+            let name = if self.is_emphasized(workspace) {
+                name.font(cosmic::iced::Font {
+                    weight: cosmic::iced::font::Weight::Bold,
+                    ..Default::default()
+                })
+            } else {
+                name
+            };
+            let mut row = widget::row()
+                .spacing(8)
+                .align_y(cosmic::iced::Alignment::Center)
+                .push(name);
+            let toplevels = self.workspace_toplevels.get(&workspace.handle);
+            for toplevel in toplevels.into_iter().flatten() {
+                row = row.push(self.toplevel_icon(toplevel).size(icon_size));
+            }
+            let button = cosmic::applet::menu_button(row)
+                .on_press(Message::ActivateWorkspace(workspace.handle.clone()));
+            let item = PopupItem::Workspace(workspace.handle.clone());
+            list = list.push(Self::selectable(button.into(), &item, selected.as_ref()));
+        }
+        widget::container(list).padding(8).into()
+    }
+
+    /// Clickable popup row for a window with its icon, title and an optional trailing label.
+    fn window_row(
+        &self,
+        toplevel: &AppToplevel,
+        label: Option<String>,
+        selected: Option<&PopupItem>,
+    ) -> Element<'_, Message> {
+        let icon_size = 24;
+        let title = if toplevel.title.is_empty() {
+            self.display_name(toplevel)
+        } else {
+            toplevel.title.clone()
+        };
+        let title = widget::text(self.config.truncate_title(&title).into_owned());
+        let title = if toplevel.is_active {
+            title.font(cosmic::iced::Font {
                 weight: cosmic::iced::font::Weight::Bold,
                 ..Default::default()
             })
         } else {
-            text
+            title
         };
+        let mut row = widget::row()
+            .spacing(8)
+            .align_y(cosmic::iced::Alignment::Center)
+            .push(self.toplevel_icon(toplevel).size(icon_size))
+            .push(title.width(Length::Fill));
+        if let Some(label) = label {
+            row = row.push(widget::text(label));
+        }
+        if toplevel.is_active {
+            row = row.push(widget::icon::from_name("object-select-symbolic").size(16));
+        }
+        let button = cosmic::applet::menu_button(row)
+            .on_press(Message::ActivateToplevel(toplevel.handle.clone()));
+        let item = PopupItem::Toplevel(toplevel.handle.clone());
+        Self::selectable(button.into(), &item, selected)
+    }
 
-        content = content.push(text);
+    /// Activate, minimize, close and move actions for the window the menu was opened for.
+    fn window_menu_popup_content(&self) -> Element<'_, Message> {
+        let toplevel = self.menu_toplevel.as_ref().and_then(|handle| {
+            self.workspace_toplevels
+                .values()
+                .flatten()
+                .find(|toplevel| toplevel.handle == *handle)
+        });
+        let Some(toplevel) = toplevel else {
+            // The window closed while its menu was open
+            return widget::container(widget::text(fl!("window-closed")))
+                .padding(8)
+                .into();
+        };
+        let handle = &toplevel.handle;
+        let item = |label: String, message: Message| {
+            cosmic::applet::menu_button(widget::text(label)).on_press(message)
+        };
+        let minimize_label = if toplevel.is_minimized {
+            fl!("restore")
+        } else {
+            fl!("minimize")
+        };
+        let mut list = widget::column()
+            .spacing(2)
+            .push(widget::text::heading(
+                self.config
+                    .truncate_title(&self.display_name(toplevel))
+                    .into_owned(),
+            ))
+            .push(item(
+                fl!("activate"),
+                Message::ActivateToplevel(handle.clone()),
+            ))
+            .push(item(
+                minimize_label,
+                Message::ToggleMinimize(handle.clone()),
+            ))
+            .push(item(fl!("close"), Message::CloseToplevel(handle.clone())));
+        let launchable = self
+            .app_icons
+            .desktop_entry(&toplevel.app_id)
+            .is_some_and(|entry| entry.launch_args().is_some());
+        if launchable {
+            list = list.push(item(
+                fl!("new-window"),
+                Message::LaunchApp(toplevel.app_id.clone()),
+            ));
+        }
+        let targets: Vec<&AppWorkspace> = self
+            .workspaces
+            .iter()
+            .filter(|workspace| !toplevel.ws_handles.contains(&workspace.handle))
+            .collect();
+        if !targets.is_empty() {
+            list = list
+                .push(widget::divider::horizontal::light())
+                .push(widget::text::heading(fl!("move-to-workspace")));
+            for workspace in targets {
+                let message = Message::MoveToplevel(handle.clone(), workspace.handle.clone());
+                list = list.push(item(workspace.name.clone(), message));
+            }
+        }
+        widget::container(list).padding(8).into()
+    }
+
+    /// One clickable row per window showing its icon, title and workspace.
+    fn window_list_popup_content(&self) -> Element<'_, Message> {
+        let selected = self.selected_item();
+        let mut list = widget::column().spacing(2);
+        for workspace in &self.workspaces {
+            let toplevels = self.workspace_toplevels.get(&workspace.handle);
+            for toplevel in toplevels.into_iter().flatten() {
+                let label = Some(workspace.name.clone());
+                list = list.push(self.window_row(toplevel, label, selected.as_ref()));
+            }
+        }
+        widget::container(list).padding(8).into()
+    }
+
+    /// One section per workspace listing its windows.
+    fn overview_popup_content(&self) -> Element<'_, Message> {
+        let selected = self.selected_item();
+        let mut list = widget::column().spacing(2);
+        for (index, workspace) in self.workspaces.iter().enumerate() {
+            if index > 0 {
+                list = list.push(widget::divider::horizontal::light());
+            }
+            let name = widget::text::heading(workspace.name.clone());
+            let button = cosmic::applet::menu_button(name)
+                .on_press(Message::ActivateWorkspace(workspace.handle.clone()));
+            let item = PopupItem::Workspace(workspace.handle.clone());
+            list = list.push(Self::selectable(button.into(), &item, selected.as_ref()));
+            let toplevels = self.workspace_toplevels.get(&workspace.handle);
+            for toplevel in toplevels.into_iter().flatten() {
+                list = list.push(self.window_row(toplevel, None, selected.as_ref()));
+            }
+        }
+        widget::container(list).padding(8).into()
+    }
+
+    /// Quick settings shown in the popup opened from the gear button.
+    fn settings_popup_content(&self) -> Element<'_, Message> {
+        let content = widget::list_column()
+            .add(widget::settings::item(
+                fl!("numbers-mode"),
+                widget::toggler(self.config.numbers_mode).on_toggle(Message::SetNumbersMode),
+            ))
+            .add(widget::settings::item(
+                fl!("dim-minimized"),
+                widget::toggler(self.config.dim_minimized).on_toggle(Message::SetDimMinimized),
+            ))
+            .add(
+                widget::button::standard(fl!("refresh-applications"))
+                    .on_press(Message::RefreshDesktopEntries),
+            );
+        widget::container(content).padding(8).into()
+    }
+
+    /// App ids of every window currently shown.
+    fn visible_app_ids(&self) -> Vec<String> {
+        self.workspace_toplevels
+            .values()
+            .flatten()
+            .filter(|toplevel| !toplevel.app_id.is_empty())
+            .map(|toplevel| toplevel.app_id.clone())
+            .collect()
+    }
+
+    /// Starts background icon resolution for each of `app_ids` that has no cached icon yet.
+    fn resolve_icons(&mut self, app_ids: Vec<String>) -> Task<cosmic::Action<Message>> {
+        let mut tasks = Vec::new();
+        for app_id in app_ids {
+            if let Some(resolve) = self.app_icons.resolve_icon_if_missing(&app_id) {
+                tasks.push(Task::perform(resolve, move |resolved| {
+                    cosmic::Action::App(Message::IconResolved(app_id, resolved))
+                }));
+            }
+        }
+        Task::batch(tasks)
+    }
+
+    /// Starts background icon resolution for every shown app id that has no cached icon yet.
+    fn resolve_missing_icons(&mut self) -> Task<cosmic::Action<Message>> {
+        self.resolve_icons(self.visible_app_ids())
+    }
+
+    /// Windows of a workspace shown in the panel, see [`Config::hide_minimized`]
+    fn get_workspace_toplevels(&self, workspace: &AppWorkspace) -> Vec<AppToplevel> {
+        let res = self.workspace_toplevels.get(&workspace.handle);
+        res.into_iter()
+            .flatten()
+            .filter(|toplevel| self.config.shows_in_panel(toplevel))
+            .cloned()
+            .collect()
+    }
+
+    fn new_workspace_button(&self, index: usize, workspace: &AppWorkspace) -> Element<'_, Message> {
+        let icon_size = self.icon_size();
+        let text_size = self.text_size();
+        let (padding_major, padding_minor) = self.core.applet.suggested_padding(true);
+        let horizontal = self.core.applet.is_horizontal();
+        let padding = if horizontal {
+            [padding_minor as f32, padding_major as f32]
+        } else {
+            [padding_major as f32, padding_minor as f32]
+        };
+        let style = WorkspaceStyle {
+            horizontal,
+            padding,
+            spacing: self.core.applet.spacing as f32 * self.config.icon_spacing_scale,
+            icon_size,
+            text_size,
+            show_name: self.config.show_workspace_names,
+            show_window_count: self.config.show_window_count,
+            highlight: self.config.active_highlight,
+            border_width: self.config.active_border_width,
+            active_color: self
+                .config
+                .workspace_color(&workspace.name, index)
+                .or_else(|| self.config.active_color()),
+            radius: self.config.active_radius,
+            inactive_opacity: self.config.inactive_opacity,
+        };
 
         let ws_top_levels = self.get_workspace_toplevels(workspace);
+        let is_active = self.is_emphasized(workspace);
+        let dim_alpha = (!is_active && self.config.dim_inactive_icons)
+            .then(|| 1.0 - self.config.inactive_opacity);
+        let mut app_icons = Vec::new();
+        if self.config.icon_mode == IconMode::ActiveOnly {
+            if let Some(icon) = self.active_icon_only(&ws_top_levels, icon_size, text_size) {
+                app_icons.push(Self::dimmed(icon, dim_alpha));
+            }
+        } else if self.config.stacked_icons {
+            let cluster = self.stacked_icons(&ws_top_levels, icon_size, text_size);
+            app_icons.push(Self::dimmed(cluster, dim_alpha));
+        } else {
+            for toplevel in &ws_top_levels {
+                let icon = self.new_application_icon_element(toplevel, icon_size);
+                app_icons.push(Self::dimmed(icon, dim_alpha));
+            }
+        }
+
+        WorkspaceWidget {
+            label: self
+                .config
+                .workspace_label(&workspace.name, index)
+                .into_owned(),
+            toplevels: &ws_top_levels,
+            icon: self
+                .config
+                .workspace_icons
+                .get(&(index + 1))
+                .and_then(|value| self.app_icons.get_named_icon(value)),
+            is_active,
+            is_drop_target: self.dragging.is_some()
+                && self.drop_target.as_ref() == Some(&workspace.handle),
+            style,
+        }
+        .view(app_icons)
+    }
 
-        if !ws_top_levels.is_empty() {
-            content = content.push(widget::horizontal_space().width(spacing + 2.0));
+    /// Overlapping icon cluster of up to [`STACKED_ICONS_MAX`] windows, followed by the number
+    /// of windows left out. Each icon keeps its own tooltip, clicks activate the workspace.
+    fn stacked_icons(
+        &self,
+        toplevels: &[AppToplevel],
+        icon_size: u16,
+        text_size: u16,
+    ) -> Element<'_, Message> {
+        let overlap = icon_size as f32 * STACKED_ICONS_OVERLAP;
+        let mut cluster: Vec<Element<'_, Message>> = toplevels
+            .iter()
+            .take(STACKED_ICONS_MAX)
+            .map(|toplevel| self.new_application_icon_element(toplevel, icon_size))
+            .collect();
+        let hidden = toplevels.len().saturating_sub(STACKED_ICONS_MAX);
+        if hidden > 0 {
+            let gap = overlap + 2.0;
+            cluster.push(widget::Space::new(gap, gap).into());
+            cluster.push(widget::text(format!("+{hidden}")).size(text_size).into());
         }
+        self.panel_flex(cluster, -overlap)
+    }
 
-        for toplevel in ws_top_levels {
-            let element = self.new_application_icon_element(
-                toplevel.app_id.as_str(),
-                toplevel.is_active,
-                icon_size,
+    /// Icon of the focused window, or of the first one if none is focused, followed by a "+N"
+    /// for the other windows that opens the overview popup.
+    fn active_icon_only(
+        &self,
+        toplevels: &[AppToplevel],
+        icon_size: u16,
+        text_size: u16,
+    ) -> Option<Element<'_, Message>> {
+        let shown = toplevels
+            .iter()
+            .find(|toplevel| toplevel.is_active)
+            .or_else(|| toplevels.first())?;
+        let mut children = vec![self.new_application_icon_element(shown, icon_size)];
+        let others = toplevels.len() - 1;
+        if others > 0 {
+            let count = widget::text(format!("+{others}")).size(text_size);
+            children.push(
+                widget::mouse_area(count)
+                    .on_press(Message::TogglePopup)
+                    .into(),
             );
-            content = content.push(element);
         }
+        Some(self.panel_flex(children, 2.0))
+    }
 
-        let is_active = workspace.is_active;
-        let container = widget::container(content)
+    /// Lays `children` out along the panel, as a row on horizontal panels and as a column on
+    /// vertical ones.
+    fn panel_flex<'a>(
+        &self,
+        children: Vec<Element<'a, Message>>,
+        spacing: f32,
+    ) -> Element<'a, Message> {
+        workspace_widget::flex(children, spacing, self.core.applet.is_horizontal())
+    }
+
+    /// Thin divider across the panel between two workspaces, `length` long.
+    fn workspace_separator(&self, length: u16) -> Element<'static, Message> {
+        let length = length as f32;
+        let size = if self.core.applet.is_horizontal() {
+            (1.0, length)
+        } else {
+            (length, 1.0)
+        };
+        widget::container(widget::Space::new(size.0, size.1))
+            .style(|theme: &Theme| widget::container::Style {
+                background: Some(cosmic::iced::Color::from(theme.cosmic().bg_divider()).into()),
+                ..Default::default()
+            })
+            .into()
+    }
+
+    /// Renders a workspace as just its 1-based number, colored by occupancy.
+    fn new_workspace_number(
+        &self,
+        index: usize,
+        workspace: &AppWorkspace,
+        text_size: u16,
+    ) -> Element<'_, Message> {
+        let occupancy = if self.is_emphasized(workspace) {
+            Occupancy::Active
+        } else if !self.get_workspace_toplevels(workspace).is_empty() {
+            Occupancy::Occupied
+        } else {
+            Occupancy::Empty
+        };
+
+        let text = widget::text(format!("{}", index + 1)).size(text_size);
+        let text = if occupancy == Occupancy::Active {
+            text.font(cosmic::iced::Font {
+                weight: cosmic::iced::font::Weight::Bold,
+                ..Default::default()
+            })
+        } else {
+            text
+        };
+
+        let (padding_major, padding_minor) = self.core.applet.suggested_padding(true);
+        let padding = if self.core.applet.is_horizontal() {
+            [padding_minor as f32, padding_major as f32 * 0.5]
+        } else {
+            [padding_major as f32 * 0.5, padding_minor as f32]
+        };
+        widget::container(text)
             .padding(padding)
-            .style(move |theme| {
-                let cosmic = theme.cosmic();
-                widget::container::Style {
-                    background: None,
-                    text_color: if is_active {
-                        Some(cosmic.on_bg_color().into())
-                    } else {
-                        Some(cosmic::iced::Color {
-                            a: 0.5,
-                            ..cosmic.on_bg_color().into()
-                        })
-                    },
-                    border: cosmic::iced_core::Border {
-                        width: if is_active { 2.0 } else { 0.0 },
-                        color: if is_active {
-                            cosmic.accent_color().into()
-                        } else {
-                            cosmic::iced::Color::TRANSPARENT
-                        },
-                        radius: cosmic.radius_s().into(),
-                    },
-                    ..Default::default()
-                }
-            });
-        container.into()
+            .style(move |theme| widget::container::Style {
+                text_color: Some(occupancy.color(theme)),
+                ..Default::default()
+            })
+            .into()
     }
 
     fn new_application_icon_element(
         &self,
-        app_id: &str,
-        is_active: bool,
+        toplevel: &AppToplevel,
         icon_size: u16,
     ) -> Element<'_, Message> {
-        let icon = self.app_icons.get_icon(app_id).size(icon_size);
-        let container = widget::container(icon).center(icon_size as f32 + 4.0);
-        if is_active {
+        let container_size = icon_size as f32 + 4.0;
+        let radius = self.config.icon_radius;
+        // Shrink the icon to fit within the circle of round borders
+        let icon_size = if radius.is_round() {
+            (container_size * std::f32::consts::FRAC_1_SQRT_2).floor() as u16
+        } else {
+            icon_size
+        };
+        let icon = self.toplevel_icon(toplevel).size(icon_size);
+        let icon: Element<'_, Message> = if toplevel.is_minimized && self.config.dim_minimized {
+            Self::dim_overlay(icon.into(), MINIMIZED_DIM_ALPHA)
+        } else {
+            icon.into()
+        };
+        let icon = if self.is_new_window(toplevel) {
+            Self::new_window_dot(icon)
+        } else {
+            icon
+        };
+        let icon = if self.dragging.as_ref() == Some(&toplevel.handle) {
+            Self::dim_overlay(icon, DRAGGED_DIM_ALPHA)
+        } else {
+            icon
+        };
+        let container = widget::container(icon).center(container_size);
+        let is_active = toplevel.is_active;
+        let pulse_alpha = self.pulse_alpha(toplevel);
+        let container = if is_active || pulse_alpha.is_some() {
+            container.style(move |theme: &Theme| {
+                let cosmic = theme.cosmic();
+                let (width, color) = if is_active {
+                    (1.5, cosmic.accent_color().into())
+                } else {
+                    (0.0, cosmic::iced::Color::TRANSPARENT)
+                };
+                let background = pulse_alpha.map(|a| {
+                    let color = cosmic::iced::Color::from(cosmic.accent_color());
+                    cosmic::iced::Color { a, ..color }.into()
+                });
+                widget::container::Style {
+                    background,
+                    text_color: None,
+                    border: cosmic::iced_core::Border {
+                        width,
+                        color,
+                        radius: radius.radius(cosmic).into(),
+                    },
+                    ..Default::default()
+                }
+            })
+        } else {
             container
-                .style(move |theme: &Theme| {
-                    let cosmic = theme.cosmic();
-                    widget::container::Style {
-                        background: None,
-                        text_color: None,
-                        border: cosmic::iced_core::Border {
-                            width: 1.5,
-                            color: cosmic.accent_color().into(),
-                            radius: cosmic.radius_xs().into(),
-                        },
-                        ..Default::default()
-                    }
-                })
-                .into()
+        };
+        let tooltip = widget::tooltip(
+            container,
+            widget::text(self.icon_label(toplevel)),
+            widget::tooltip::Position::Bottom,
+        );
+        // Pressing starts a drag, releasing over a workspace button drops it there. The flat
+        // layout has no workspace buttons, pressing activates the window right away.
+        let on_press = if self.config.layout == Layout::Flat {
+            Message::ActivateToplevel(toplevel.handle.clone())
         } else {
-            container.into()
+            Message::DragToplevel(toplevel.handle.clone())
+        };
+        widget::mouse_area(tooltip)
+            .on_press(on_press)
+            .on_middle_press(Message::ToggleMinimize(toplevel.handle.clone()))
+            .on_right_press(Message::OpenWindowMenu(toplevel.handle.clone()))
+            .into()
+    }
+
+    /// Stacks a small accent colored dot on the top right corner of `content`.
+    fn new_window_dot(content: Element<'_, Message>) -> Element<'_, Message> {
+        let dot = widget::container(widget::Space::new(6.0, 6.0)).style(|theme: &Theme| {
+            let cosmic = theme.cosmic();
+            widget::container::Style {
+                background: Some(cosmic::iced::Color::from(cosmic.accent_color()).into()),
+                border: cosmic::iced_core::Border {
+                    radius: cosmic.radius_xl().into(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+        });
+        let corner = widget::container(dot)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .align_x(cosmic::iced::alignment::Horizontal::Right)
+            .align_y(cosmic::iced::alignment::Vertical::Top);
+        cosmic::iced::widget::stack![content, corner].into()
+    }
+
+    /// Dims `content` with [`Self::dim_overlay`] if an alpha is given.
+    fn dimmed(content: Element<'_, Message>, alpha: Option<f32>) -> Element<'_, Message> {
+        match alpha {
+            Some(alpha) => Self::dim_overlay(content, alpha),
+            None => content,
         }
     }
+
+    /// Dims `content` by stacking a translucent layer in the panel background color on top of it.
+    fn dim_overlay(content: Element<'_, Message>, alpha: f32) -> Element<'_, Message> {
+        let overlay = widget::container(widget::Space::new(Length::Fill, Length::Fill))
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .style(move |theme: &Theme| {
+                let cosmic = theme.cosmic();
+                widget::container::Style {
+                    background: Some(
+                        cosmic::iced::Color {
+                            a: alpha,
+                            ..cosmic.bg_color().into()
+                        }
+                        .into(),
+                    ),
+                    ..Default::default()
+                }
+            });
+        cosmic::iced::widget::stack![content, overlay].into()
+    }
 }
 
 impl cosmic::Application for AppModel {
@@ -182,25 +1219,35 @@ impl cosmic::Application for AppModel {
         core: cosmic::Core,
         _flags: Self::Flags,
     ) -> (Self, Task<cosmic::Action<Self::Message>>) {
+        let (config_handler, config) = Self::load_config();
         // Construct the app model with the runtime's core.
-        let app = AppModel {
+        let mut app = AppModel {
+            raw_toplevels: Arc::default(),
             workspace_toplevels: HashMap::new(),
             workspaces: Vec::new(),
             core,
-            config: cosmic_config::Config::new(Self::APP_ID, Config::VERSION)
-                .map(|context| match Config::get_entry(&context) {
-                    Ok(config) => config,
-                    Err((_errors, config)) => {
-                        // for why in errors {
-                        //     tracing::error!(%why, "error loading app config");
-                        // }
-
-                        config
-                    }
-                })
-                .unwrap_or_default(),
+            config,
+            config_handler,
+            popup: None,
             app_icons: Icons::new(),
+            wayland_sender: None,
+            pager_scroll: KineticScroll::default(),
+            switch_scroll: 0.0,
+            modifiers: Modifiers::empty(),
+            seen_toplevels: None,
+            last_sequence: None,
+            output_scale: 1,
+            pulses: HashMap::new(),
+            dragging: None,
+            drag_origin: None,
+            drop_target: None,
+            popup_selection: None,
+            menu_toplevel: None,
+            #[cfg(feature = "dbus-service")]
+            state_publisher: None,
+            pending_activation: None,
         };
+        app.load_config_icons();
 
         (app, Task::none())
     }
@@ -212,15 +1259,73 @@ impl cosmic::Application for AppModel {
     /// activated by selectively appending to the subscription batch, and will
     /// continue to execute for the duration that they remain in the batch.
     fn subscription(&self) -> Subscription<Self::Message> {
-        let subscriptions = vec![
+        let mut subscriptions = vec![
             // Watch for application configuration changes.
             self.core()
                 .watch_config::<Config>(Self::APP_ID)
                 .map(|update| Message::UpdateConfig(update.config)),
             // Workspace subscription
             wayland_subscription::workspace_subscription().map(Message::WaylandEvent),
+            // Reload everything on SIGUSR1
+            signal_subscription::reload_subscription().map(|()| Message::ReloadAll),
+            // Watch for icon theme switches
+            self.core()
+                .watch_config::<cosmic::config::CosmicTk>(cosmic::config::ID)
+                .map(|update| Message::IconThemeChanged(update.config.icon_theme)),
+            // Watch for installed, updated or removed applications
+            desktop_watcher_subscription::desktop_watcher_subscription()
+                .map(Message::DesktopFilesChanged),
+            // Publish the state on the session bus
+            #[cfg(feature = "dbus-service")]
+            crate::dbus_service::subscription().map(Message::DbusServiceReady),
         ];
 
+        // Resolve queued icons a few at a time, only ticking while any are queued
+        if self.app_icons.has_queued() {
+            subscriptions.push(
+                cosmic::iced::time::every(ICON_TICK_INTERVAL).map(|_| Message::ResolveQueuedIcons),
+            );
+        }
+
+        // Only ticks while a pulse is running
+        if !self.pulses.is_empty() {
+            subscriptions
+                .push(cosmic::iced::time::every(PULSE_TICK_INTERVAL).map(|_| Message::PulseTick));
+        }
+
+        if self.popup.is_some() {
+            subscriptions.push(cosmic::iced::event::listen_with(|event, _status, _id| {
+                use cosmic::iced::keyboard::{Event, Key, key::Named};
+                let cosmic::iced::Event::Keyboard(Event::KeyPressed { key, .. }) = event else {
+                    return None;
+                };
+                let key = match key {
+                    Key::Named(Named::ArrowUp) => PopupKey::Up,
+                    Key::Named(Named::ArrowDown) => PopupKey::Down,
+                    Key::Named(Named::Enter) => PopupKey::Activate,
+                    Key::Named(Named::Escape) => PopupKey::Close,
+                    _ => return None,
+                };
+                Some(Message::PopupKey(key))
+            }));
+        }
+
+        if self.config.smooth_scroll {
+            subscriptions.push(cosmic::iced::event::listen_with(
+                |event, _status, _id| match event {
+                    cosmic::iced::Event::Keyboard(
+                        cosmic::iced::keyboard::Event::ModifiersChanged(modifiers),
+                    ) => Some(Message::ModifiersChanged(modifiers)),
+                    _ => None,
+                },
+            ));
+            if self.pager_scroll.is_animating() {
+                subscriptions.push(
+                    cosmic::iced::time::every(SCROLL_TICK_INTERVAL).map(|_| Message::PagerTick),
+                );
+            }
+        }
+
         Subscription::batch(subscriptions)
     }
 
@@ -231,25 +1336,411 @@ impl cosmic::Application for AppModel {
     /// tasks are finished.
     fn update(&mut self, message: Self::Message) -> Task<cosmic::Action<Self::Message>> {
         match message {
-            Message::UpdateConfig(config) => {
+            Message::UpdateConfig(mut config) => {
+                config.validate();
+                config.apply_preset();
+                if config.group_output_filter != self.config.group_output_filter {
+                    if let Some(sender) = &self.wayland_sender {
+                        let filter = config.group_output_filter;
+                        sender.send(WaylandRequest::SetGroupOutputFilter(filter));
+                    }
+                }
+                if config.unassigned_windows != self.config.unassigned_windows {
+                    if let Some(sender) = &self.wayland_sender {
+                        let unassigned = config.unassigned_windows;
+                        sender.send(WaylandRequest::SetUnassignedWindows(unassigned));
+                    }
+                }
                 self.config = config;
+                self.load_config_icons();
+                // Filters and sorting apply right away rather than with the next window update
+                self.rebuild_workspace_toplevels();
+                self.publish_state();
+                // Windows that are no longer hidden may need their icons
+                return self.resolve_missing_icons();
+            }
+            Message::WaylandEvent(WaylandEvent::Connected(sender)) => {
+                sender.send(WaylandRequest::SetGroupOutputFilter(
+                    self.config.group_output_filter,
+                ));
+                sender.send(WaylandRequest::SetUnassignedWindows(
+                    self.config.unassigned_windows,
+                ));
+                self.wayland_sender = Some(sender);
             }
             Message::WaylandEvent(WaylandEvent::WorkspacesChanged(workspaces)) => {
+                // The compositor's state supersedes any optimistic activation
+                self.pending_activation = None;
                 self.workspaces = workspaces;
-                self.workspaces.sort_by_key(|ws| ws.coordinates);
+                self.workspaces.sort_by(AppWorkspace::cmp_coordinates);
+                self.publish_state();
             }
             Message::WaylandEvent(WaylandEvent::ToplevelsUpdated(ws_toplevels)) => {
-                let mut transformed = HashMap::new();
-                for (ws_id, toplevels_by_id) in ws_toplevels {
-                    let mut toplevels: Vec<AppToplevel> = Vec::new();
-                    for toplevel in toplevels_by_id.values() {
-                        self.app_icons.load_icon_if_missing(&toplevel.app_id);
-                        toplevels.push(toplevel.clone());
+                self.raw_toplevels = ws_toplevels;
+                self.rebuild_workspace_toplevels();
+                self.start_pulses();
+                match &mut self.seen_toplevels {
+                    // Forget closed windows so the seen set doesn't grow forever
+                    Some(seen) => seen.retain(|handle| {
+                        self.workspace_toplevels
+                            .values()
+                            .flatten()
+                            .any(|toplevel| &toplevel.handle == handle)
+                    }),
+                    None => self.acknowledge_new_windows(),
+                }
+                self.publish_state();
+                return self.resolve_missing_icons();
+            }
+            Message::WaylandEvent(WaylandEvent::OutputScaleChanged(scale)) => {
+                self.output_scale = scale;
+                if self.app_icons.set_lookup_size(self.icon_size(), scale) {
+                    let app_ids = self.visible_app_ids();
+                    self.app_icons.invalidate_all(app_ids);
+                }
+            }
+            Message::WaylandEvent(WaylandEvent::ActiveToplevelChanged { old, new }) => {
+                // Only the focus styling changes, the window order stays as it is
+                wayland_subscription::apply_active_change(
+                    Arc::make_mut(&mut self.raw_toplevels)
+                        .values_mut()
+                        .flat_map(|toplevels| toplevels.values_mut())
+                        .chain(self.workspace_toplevels.values_mut().flatten()),
+                    old.as_ref(),
+                    new.as_ref(),
+                );
+                self.publish_state();
+            }
+            #[cfg(feature = "dbus-service")]
+            Message::DbusServiceReady(publisher) => {
+                self.state_publisher = Some(publisher);
+                self.publish_state();
+            }
+            Message::ReloadAll => {
+                log::info!("SIGUSR1 received, reloading config, desktop entries and icons");
+                let (handler, config) = Self::load_config();
+                self.config_handler = handler;
+                self.acknowledge_new_windows();
+                // Every icon is resolved again once the desktop entries are rescanned
+                return Task::batch([
+                    <Self as cosmic::Application>::update(self, Message::UpdateConfig(config)),
+                    <Self as cosmic::Application>::update(self, Message::RefreshDesktopEntries),
+                ]);
+            }
+            Message::RefreshDesktopEntries => {
+                // Scanning every data dir is slow, so the old index stays in use meanwhile
+                let scan = async {
+                    tokio::task::spawn_blocking(DesktopMatcher::new)
+                        .await
+                        .map(Arc::new)
+                        .ok()
+                };
+                return Task::future(scan).and_then(|matcher| {
+                    Task::done(cosmic::Action::App(Message::DesktopEntriesLoaded(matcher)))
+                });
+            }
+            Message::DesktopEntriesLoaded(matcher) => {
+                log::info!("desktop entries refreshed, reloading icons");
+                let app_ids = self.visible_app_ids();
+                self.app_icons.set_desktop_matcher(matcher);
+                self.app_icons.clear_path_cache();
+                self.app_icons.invalidate_all(app_ids);
+            }
+            Message::DesktopFilesChanged(paths) => {
+                self.app_icons.desktop_files_changed(&paths);
+                return self.resolve_missing_icons();
+            }
+            Message::ToggleSettingsPopup => {
+                return self.toggle_popup(PopupKind::Settings);
+            }
+            Message::PanelClicked => match self.config.click_action {
+                ClickAction::TogglePopup => return self.toggle_popup(PopupKind::Overview),
+                ClickAction::ShowOverview => {
+                    return <Self as cosmic::Application>::update(self, Message::OpenOverview);
+                }
+                ClickAction::None => {}
+            },
+            Message::OpenOverview => {
+                // Falls back to the applet's own overview popup if the command is missing
+                if let Err(err) = Self::spawn_detached(COSMIC_WORKSPACES, &[]) {
+                    log::warn!("failed to launch {COSMIC_WORKSPACES}: {err}");
+                    return <Self as cosmic::Application>::update(self, Message::TogglePopup);
+                }
+            }
+            Message::TogglePopup => {
+                return self.toggle_popup(PopupKind::Overview);
+            }
+            Message::ToggleOverflowPopup => {
+                return self.toggle_popup(PopupKind::Overflow);
+            }
+            Message::ToggleWindowList => {
+                return self.toggle_popup(PopupKind::WindowList);
+            }
+            Message::ActivateToplevel(handle) => {
+                if let Some(sender) = &self.wayland_sender {
+                    sender.send(WaylandRequest::ActivateToplevel(handle));
+                }
+                if let Some((id, _)) = self.popup.take() {
+                    return destroy_popup(id);
+                }
+            }
+            Message::ToggleMinimize(handle) => {
+                let Some(sender) = &self.wayland_sender else {
+                    return Task::none();
+                };
+                let toplevels = Arc::make_mut(&mut self.raw_toplevels)
+                    .values_mut()
+                    .filter_map(|toplevels| toplevels.get_mut(&handle))
+                    .chain(
+                        self.workspace_toplevels
+                            .values_mut()
+                            .flatten()
+                            .filter(|toplevel| toplevel.handle == handle),
+                    );
+                let mut minimized = None;
+                // Dim or undim right away, the next toplevel update confirms the state
+                for toplevel in toplevels {
+                    let minimize = *minimized.get_or_insert(!toplevel.is_minimized);
+                    toplevel.is_minimized = minimize;
+                }
+                if let Some(minimized) = minimized {
+                    sender.send(WaylandRequest::SetMinimized(handle, minimized));
+                }
+                return self.close_window_menu();
+            }
+            Message::OpenWindowMenu(handle) => {
+                let other_window = self.menu_toplevel.as_ref() != Some(&handle);
+                self.menu_toplevel = Some(handle);
+                if other_window && matches!(self.popup, Some((_, PopupKind::WindowMenu))) {
+                    // The open menu switches over to the newly clicked window
+                    return Task::none();
+                }
+                return self.toggle_popup(PopupKind::WindowMenu);
+            }
+            Message::CloseToplevel(handle) => {
+                if let Some(sender) = &self.wayland_sender {
+                    sender.send(WaylandRequest::CloseToplevel(handle));
+                }
+                return self.close_window_menu();
+            }
+            Message::LaunchApp(app_id) => {
+                let entry = self.app_icons.desktop_entry(&app_id);
+                let Some(mut args) = entry.and_then(|entry| entry.launch_args()) else {
+                    log::warn!("app_id={app_id} launch ignored - no desktop entry with Exec");
+                    return self.close_window_menu();
+                };
+                if entry.is_some_and(|entry| entry.terminal) {
+                    let terminal = std::env::var("TERMINAL")
+                        .ok()
+                        .filter(|terminal| !terminal.is_empty())
+                        .unwrap_or_else(|| DEFAULT_TERMINAL.to_string());
+                    args.splice(0..0, [terminal, "-e".to_string()]);
+                }
+                // Run directly rather than through a shell, so nothing in Exec gets interpreted
+                if let Err(err) = Self::spawn_detached(&args[0], &args[1..]) {
+                    log::warn!("app_id={app_id} failed to launch {}: {err}", args[0]);
+                }
+                return self.close_window_menu();
+            }
+            Message::MoveToplevel(handle, ws_handle) => {
+                if let Some(sender) = &self.wayland_sender {
+                    sender.send(WaylandRequest::MoveToplevel(handle, ws_handle));
+                }
+                return self.close_window_menu();
+            }
+            Message::PopupKey(key) => {
+                let items = self.popup_items();
+                match key {
+                    PopupKey::Up | PopupKey::Down if items.is_empty() => {}
+                    // Wrap around at both ends of the list
+                    PopupKey::Down => {
+                        let len = items.len();
+                        let next = self.popup_selection.map_or(0, |index| (index + 1) % len);
+                        self.popup_selection = Some(next);
+                    }
+                    PopupKey::Up => {
+                        let len = items.len();
+                        let previous = self
+                            .popup_selection
+                            .map_or(len - 1, |index| (index % len + len - 1) % len);
+                        self.popup_selection = Some(previous);
+                    }
+                    PopupKey::Activate => {
+                        let message = match self.popup_selection.and_then(|i| items.get(i)) {
+                            Some(PopupItem::Workspace(handle)) => {
+                                Message::ActivateWorkspace(handle.clone())
+                            }
+                            Some(PopupItem::Toplevel(handle)) => {
+                                Message::ActivateToplevel(handle.clone())
+                            }
+                            None => return Task::none(),
+                        };
+                        return <Self as cosmic::Application>::update(self, message);
+                    }
+                    PopupKey::Close => {
+                        if let Some((id, _)) = self.popup.take() {
+                            return destroy_popup(id);
+                        }
+                    }
+                }
+            }
+            Message::PopupClosed(id) => {
+                if self.popup.is_some_and(|(popup_id, _)| popup_id == id) {
+                    self.popup = None;
+                }
+            }
+            Message::SetNumbersMode(numbers_mode) => {
+                if let Some(handler) = &self.config_handler {
+                    if let Err(err) = self.config.set_numbers_mode(handler, numbers_mode) {
+                        log::error!("failed to write numbers_mode: {err}");
+                    }
+                }
+            }
+            Message::SetDimMinimized(dim_minimized) => {
+                if let Some(handler) = &self.config_handler {
+                    if let Err(err) = self.config.set_dim_minimized(handler, dim_minimized) {
+                        log::error!("failed to write dim_minimized: {err}");
+                    }
+                }
+            }
+            Message::ResolveQueuedIcons => {
+                let app_ids = self.app_icons.take_queued(ICONS_PER_TICK);
+                return self.resolve_icons(app_ids);
+            }
+            Message::IconThemeChanged(theme) => {
+                if self.app_icons.set_icon_theme(&theme) {
+                    let app_ids = self.visible_app_ids();
+                    self.app_icons.invalidate_all(app_ids);
+                    self.load_config_icons();
+                }
+            }
+            Message::DragToplevel(handle) => {
+                // The icon takes the press, so the workspace it is on is the hovered one
+                self.drag_origin = self.drop_target.clone();
+                self.dragging = Some(handle);
+            }
+            Message::DragEnter(ws_handle) => {
+                self.drop_target = Some(ws_handle);
+            }
+            Message::DragLeave(ws_handle) => {
+                // The next button's enter may arrive before this leave
+                if self.drop_target.as_ref() == Some(&ws_handle) {
+                    self.drop_target = None;
+                }
+            }
+            Message::DragCancel => {
+                self.cancel_drag();
+            }
+            Message::DropOnWorkspace(ws_handle) => {
+                let Some(handle) = self.dragging.take() else {
+                    return Task::none();
+                };
+                let is_click = match self.drag_origin.take() {
+                    Some(origin) => origin == ws_handle,
+                    // Pressed before the pointer was seen entering a workspace
+                    None => self
+                        .workspace_toplevels
+                        .get(&ws_handle)
+                        .is_some_and(|toplevels| toplevels.iter().any(|tl| tl.handle == handle)),
+                };
+                if is_click {
+                    // A click on an icon without dragging it away activates its workspace
+                    let message = Message::ActivateWorkspace(ws_handle);
+                    return <Self as cosmic::Application>::update(self, message);
+                }
+                if let Some(sender) = &self.wayland_sender {
+                    sender.send(WaylandRequest::MoveToplevel(handle, ws_handle));
+                }
+            }
+            Message::AcknowledgeNewWindows => {
+                self.acknowledge_new_windows();
+            }
+            Message::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers;
+            }
+            Message::PagerScrolled(viewport) => {
+                // The pager scrolls along the panel
+                let (offset, content, bounds) = if self.core.applet.is_horizontal() {
+                    (
+                        viewport.absolute_offset().x,
+                        viewport.content_bounds().width,
+                        viewport.bounds().width,
+                    )
+                } else {
+                    (
+                        viewport.absolute_offset().y,
+                        viewport.content_bounds().height,
+                        viewport.bounds().height,
+                    )
+                };
+                self.pager_scroll.set_viewport(
+                    offset,
+                    content,
+                    bounds,
+                    self.visible_workspace_count(),
+                );
+            }
+            Message::PagerWheel(delta) => {
+                if self.modifiers.shift() {
+                    self.pager_scroll.fling(delta);
+                    return Task::none();
+                }
+                // Scroll-to-switch, one workspace per wheel notch or per swipe distance
+                let step = match delta {
+                    ScrollDelta::Lines { y, .. } if y == 0.0 => 0.0,
+                    ScrollDelta::Lines { y, .. } => -y.signum(),
+                    ScrollDelta::Pixels { y, .. } => {
+                        self.switch_scroll -= y;
+                        let step = (self.switch_scroll / SWITCH_SCROLL_PIXELS).trunc();
+                        self.switch_scroll -= step * SWITCH_SCROLL_PIXELS;
+                        step
                     }
-                    toplevels.sort_by_key(|tl| tl.coordinates);
-                    transformed.insert(ws_id, toplevels);
+                };
+                if step != 0.0 {
+                    return self.switch_workspace(step as isize);
+                }
+            }
+            Message::PulseTick => {
+                self.pulses
+                    .retain(|_, started| started.elapsed() < PULSE_DURATION);
+            }
+            Message::PagerTick => {
+                let offset = self.pager_scroll.tick();
+                let offset = if self.core.applet.is_horizontal() {
+                    AbsoluteOffset { x: offset, y: 0.0 }
+                } else {
+                    AbsoluteOffset { x: 0.0, y: offset }
+                };
+                return cosmic::iced::widget::scrollable::scroll_to(
+                    PAGER_SCROLL_ID.clone(),
+                    offset,
+                );
+            }
+            Message::IconResolved(app_id, resolved) => {
+                self.app_icons.insert_resolved(app_id, resolved);
+            }
+            Message::ActivateWorkspace(handle) => {
+                let mut tasks = Vec::new();
+                if let Some(sender) = &self.wayland_sender {
+                    sender.send(WaylandRequest::ActivateWorkspace(handle.clone()));
+                    tasks.push(self.activate_optimistically(handle));
+                }
+                if let Some((id, _)) = self.popup.take() {
+                    tasks.push(destroy_popup(id));
+                }
+                return Task::batch(tasks);
+            }
+            Message::ActivationTimedOut(handle) => {
+                if let Some((_, confirmed)) = self
+                    .pending_activation
+                    .take_if(|(pending, _)| *pending == handle)
+                {
+                    log::debug!(
+                        "workspace_handle_id={} activation reverted - not confirmed",
+                        handle.id()
+                    );
+                    self.workspaces = confirmed;
                 }
-                self.workspace_toplevels = transformed;
             }
         }
         Task::none()
@@ -262,24 +1753,132 @@ impl cosmic::Application for AppModel {
     /// be drawn using the `view_window` method.
     fn view(&self) -> Element<'_, Self::Message> {
         // Use applet spacing configuration
-        let row_spacing = self.core.applet.spacing as f32;
-        let text_size = match &self.core.applet.size {
-            Size::PanelSize(panel_size) => {
-                let size = panel_size.get_applet_icon_size_with_padding(false);
-                (size as f32 * 0.4).max(10.0) as u16
-            }
-            Size::Hardcoded(_) => 14,
-        };
+        let row_spacing = self.core.applet.spacing as f32 * self.config.workspace_spacing_scale;
+        let text_size = self.text_size();
+
+        // Minimized windows may not show in the panel, see `get_workspace_toplevels`
+        let shows_no_windows = !self
+            .workspace_toplevels
+            .values()
+            .flatten()
+            .any(|toplevel| self.config.shows_in_panel(toplevel));
+        if self.config.autohide_when_empty && shows_no_windows {
+            // Rendered again with the next window update, so the applet reappears right away
+            let limits = Limits::NONE
+                .min_width(1.)
+                .min_height(1.)
+                .max_width(1.)
+                .max_height(1.);
+            return widget::autosize::autosize(
+                widget::Space::new(1.0, 1.0),
+                AUTOSIZE_MAIN_ID.clone(),
+            )
+            .limits(limits)
+            .into();
+        }
 
-        let mut row = widget::row().spacing(row_spacing);
+        let mut row: Vec<Element<'_, Message>> = Vec::new();
 
         if self.workspaces.is_empty() {
-            row = row.push(widget::text("...").size(text_size));
+            let placeholder = if self.wayland_sender.is_none() {
+                &self.config.connecting_placeholder
+            } else {
+                &self.config.empty_placeholder
+            };
+            if !placeholder.is_empty() {
+                row.push(widget::text(placeholder.as_str()).size(text_size).into());
+            }
+        } else if self.config.layout == Layout::Flat {
+            let icon_size = self.icon_size();
+            let icons = self
+                .flat_toplevels()
+                .iter()
+                .map(|toplevel| self.new_application_icon_element(toplevel, icon_size))
+                .collect();
+            let spacing = self.core.applet.spacing as f32 * self.config.icon_spacing_scale;
+            row.push(self.panel_flex(icons, spacing * 0.5));
         } else {
-            for workspace in &self.workspaces {
-                row = row.push(self.new_workspace_button(workspace));
+            let workspaces = self.panel_workspaces();
+            let visible = self.visible_workspace_count();
+            let hidden = workspaces.len() - visible;
+            let mut pager = Vec::new();
+            for (index, workspace) in workspaces.into_iter().take(visible) {
+                let button = if self.config.numbers_mode {
+                    self.new_workspace_number(index, workspace, text_size)
+                } else {
+                    self.new_workspace_button(index, workspace)
+                };
+                pager.push(
+                    widget::mouse_area(button)
+                        .on_press(Message::ActivateWorkspace(workspace.handle.clone()))
+                        .on_release(Message::DropOnWorkspace(workspace.handle.clone()))
+                        .on_enter(Message::DragEnter(workspace.handle.clone()))
+                        .on_exit(Message::DragLeave(workspace.handle.clone()))
+                        .into(),
+                );
+            }
+            if self.config.show_separators {
+                let mut separated = Vec::with_capacity(pager.len() * 2);
+                for (index, button) in pager.into_iter().enumerate() {
+                    if index > 0 {
+                        separated.push(self.workspace_separator(text_size));
+                    }
+                    separated.push(button);
+                }
+                pager = separated;
+            }
+            let pager = self.panel_flex(pager, row_spacing);
+            if self.config.smooth_scroll {
+                // The wheel is handled here so the scrollable only moves through PagerTick
+                let pager = widget::mouse_area(pager).on_scroll(Message::PagerWheel);
+                let scrollbar = Scrollbar::new().width(0).scroller_width(0);
+                let direction = if self.core.applet.is_horizontal() {
+                    Direction::Horizontal(scrollbar)
+                } else {
+                    Direction::Vertical(scrollbar)
+                };
+                row.push(
+                    widget::scrollable(pager)
+                        .id(PAGER_SCROLL_ID.clone())
+                        .direction(direction)
+                        .on_scroll(Message::PagerScrolled)
+                        .into(),
+                );
+            } else {
+                row.push(pager);
             }
+            if hidden > 0 {
+                row.push(
+                    widget::button::text(format!("+{hidden}"))
+                        .class(cosmic::theme::Button::AppletIcon)
+                        .on_press(Message::ToggleOverflowPopup)
+                        .into(),
+                );
+            }
+        }
+
+        if self.config.show_active_title {
+            if let Some(toplevel) = self.active_toplevel() {
+                let title = if toplevel.title.is_empty() {
+                    self.display_name(toplevel)
+                } else {
+                    toplevel.title.clone()
+                };
+                let title = self.config.truncate_title(&title).into_owned();
+                row.push(widget::text(title).size(text_size).into());
+            }
+        }
+
+        if self.config.show_settings_button {
+            row.push(
+                self.core
+                    .applet
+                    .icon_button("emblem-system-symbolic")
+                    .on_press(Message::ToggleSettingsPopup)
+                    .into(),
+            );
         }
+        let row = self.panel_flex(row, row_spacing);
 
         let mut limits = Limits::NONE.min_width(1.).min_height(1.);
         if let Some(b) = self.core.applet.suggested_bounds {
@@ -291,11 +1890,46 @@ impl cosmic::Application for AppModel {
             }
         }
 
-        widget::autosize::autosize(widget::container(row).padding(0), AUTOSIZE_MAIN_ID.clone())
+        // Clicks that no workspace button handled land on the panel button itself
+        let panel_button = widget::mouse_area(widget::container(row).padding(0))
+            .on_press(Message::PanelClicked)
+            .on_release(Message::DragCancel)
+            .on_middle_press(Message::ToggleWindowList)
+            .on_enter(Message::AcknowledgeNewWindows)
+            .on_exit(Message::DragCancel);
+
+        widget::autosize::autosize(panel_button, AUTOSIZE_MAIN_ID.clone())
             .limits(limits)
             .into()
     }
 
+    /// Describes the interface of the popup opened from the panel.
+    fn view_window(&self, id: Id) -> Element<'_, Self::Message> {
+        let content = match self.popup {
+            Some((popup_id, PopupKind::Settings)) if popup_id == id => {
+                self.settings_popup_content()
+            }
+            Some((popup_id, PopupKind::Overview)) if popup_id == id => {
+                self.overview_popup_content()
+            }
+            Some((popup_id, PopupKind::WindowList)) if popup_id == id => {
+                self.window_list_popup_content()
+            }
+            Some((popup_id, PopupKind::Overflow)) if popup_id == id => {
+                self.overflow_popup_content()
+            }
+            Some((popup_id, PopupKind::WindowMenu)) if popup_id == id => {
+                self.window_menu_popup_content()
+            }
+            _ => widget::text("").into(),
+        };
+        self.core.applet.popup_container(content).into()
+    }
+
+    fn on_close_requested(&self, id: Id) -> Option<Self::Message> {
+        Some(Message::PopupClosed(id))
+    }
+
     fn style(&self) -> Option<cosmic::iced_runtime::Appearance> {
         Some(cosmic::applet::style())
     }
@@ -1,9 +1,731 @@
 // SPDX-License-Identifier: MPL-2.0
 
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use cosmic::cctk::wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1;
 use cosmic::cosmic_config::{self, cosmic_config_derive::CosmicConfigEntry, CosmicConfigEntry};
+use cosmic::iced::Color;
+use serde::{Deserialize, Serialize};
+use unicode_segmentation::UnicodeSegmentation;
+use wayland_protocols::ext::workspace::v1::client::ext_workspace_handle_v1::ExtWorkspaceHandleV1;
+
+use crate::icons::DEFAULT_CACHE_CAPACITY;
+use crate::wayland_subscription::AppToplevel;
+
+/// Bundles of settings to start from, see [`Config::apply_preset`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Preset {
+    /// Every field as configured
+    #[default]
+    None,
+    /// Workspace numbers only, without empty workspaces
+    Minimal,
+    /// Names, window counts, separators and new window cues
+    Full,
+    /// One flat list of windows in the order they opened
+    ClassicTaskbar,
+}
+
+/// Which active workspaces get the active styling when several are active at once,
+/// e.g. one per monitor.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ActiveEmphasis {
+    /// Every active workspace
+    #[default]
+    All,
+    /// Only the active workspace on the output this panel is on
+    PanelOutput,
+    /// Only the active workspace holding the focused window
+    Focused,
+}
+
+/// What a primary click on the panel button, outside of a workspace, does.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClickAction {
+    /// Toggle the popup listing every workspace with its windows
+    #[default]
+    TogglePopup,
+    /// Open the COSMIC workspaces overview, or the popup if it can't be launched
+    ShowOverview,
+    /// Nothing, for a display-only bar
+    None,
+}
+
+/// How the active workspace is highlighted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ActiveHighlight {
+    /// A border around the workspace
+    #[default]
+    Border,
+    /// A filled background behind the workspace
+    Filled,
+}
+
+/// Corner radius presets of the theme.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CornerRadius {
+    None,
+    Xs,
+    #[default]
+    S,
+    M,
+    L,
+    Xl,
+}
+
+impl CornerRadius {
+    pub fn radius(self, cosmic: &cosmic::cosmic_theme::Theme) -> [f32; 4] {
+        match self {
+            CornerRadius::None => cosmic.radius_0(),
+            CornerRadius::Xs => cosmic.radius_xs(),
+            CornerRadius::S => cosmic.radius_s(),
+            CornerRadius::M => cosmic.radius_m(),
+            CornerRadius::L => cosmic.radius_l(),
+            CornerRadius::Xl => cosmic.radius_xl(),
+        }
+    }
+
+    /// Radius of at least half the size of typical panel icons, so corners are circular arcs
+    /// that cut into square content.
+    pub fn is_round(self) -> bool {
+        matches!(self, CornerRadius::L | CornerRadius::Xl)
+    }
+}
 
-#[derive(Debug, Default, Clone, CosmicConfigEntry, Eq, PartialEq)]
+/// Order of the window icons within a workspace.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToplevelSort {
+    /// By window position, windows without known geometry last. Positions are relative to
+    /// the panel's output, or to one of each window's outputs if the panel's is unknown.
+    #[default]
+    Geometry,
+    AppId,
+    Title,
+    /// In the order the windows appeared
+    Stable,
+}
+
+/// Which workspace groups a panel shows when it is limited to its own output.
+///
+/// ext-workspace only associates outputs with workspace groups, not with single workspaces,
+/// so filtering is by group. Groups without any output, e.g. left behind by a disconnected
+/// monitor, are never shown.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GroupOutputFilter {
+    /// Groups shown on the panel's output, including ones spanning other outputs as well
+    #[default]
+    Any,
+    /// Only groups shown on the panel's output alone
+    Exclusive,
+}
+
+/// What a workspace is labeled with in the panel.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LabelMode {
+    /// The name the compositor reports
+    #[default]
+    Name,
+    /// The 1-based position among the shown workspaces
+    Index,
+    /// The name, or the position if the name is empty
+    NameOrIndex,
+}
+
+/// How the panel arranges the windows.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Layout {
+    /// Grouped by workspace
+    #[default]
+    Grouped,
+    /// One list of every window regardless of workspace, like a traditional taskbar
+    Flat,
+}
+
+/// Which window icons a workspace shows in the panel.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IconMode {
+    /// One icon per window
+    #[default]
+    All,
+    /// Only the focused window's icon, or the first window's on workspaces without focus,
+    /// followed by the number of other windows
+    ActiveOnly,
+}
+
+/// Where windows that are on no known workspace are shown, e.g. briefly while the compositor
+/// moves them between workspaces.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnassignedWindows {
+    /// On the workspaces they were last seen on, new windows only once they are assigned
+    #[default]
+    KeepLast,
+    /// On the active workspace of the panel's output
+    ActiveWorkspace,
+    /// Not at all until they are assigned
+    Hide,
+}
+
+/// How windows that report no app id are shown.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnidentifiedWindows {
+    /// With a generic window icon, labeled by their title
+    #[default]
+    Generic,
+    /// Not at all
+    Hide,
+}
+
+#[derive(Debug, Clone, CosmicConfigEntry, PartialEq)]
 #[version = 1]
 pub struct Config {
     demo: String,
+    /// Bundle of settings applied on top of the defaults, fields configured to a non-default
+    /// value take precedence over it
+    pub preset: Preset,
+    /// Render minimized windows with reduced opacity
+    pub dim_minimized: bool,
+    /// Render each workspace as just its number, colored by occupancy
+    pub numbers_mode: bool,
+    /// Whether windows are grouped by workspace, the workspace options don't apply to `Flat`
+    pub layout: Layout,
+    /// Show a gear button in the panel that opens the quick settings popup
+    pub show_settings_button: bool,
+    /// Icon name or absolute path shown before the name of the workspace at a 1-based position
+    pub workspace_icons: HashMap<usize, String>,
+    /// Interval between lines written in `--statusline` mode
+    pub statusline_interval_ms: u64,
+    /// Maximum number of workspaces shown in the panel before the rest collapse behind a
+    /// "+N" button, unlimited if unset
+    pub overflow_threshold: Option<usize>,
+    /// Which active workspaces are emphasized on multi-monitor setups
+    pub active_emphasis: ActiveEmphasis,
+    /// Application icon size in pixels, derived from the panel size if unset
+    pub icon_size_override: Option<u16>,
+    /// Make the pager scrollable with inertia and snapping. While enabled, scrolling switches
+    /// workspaces and scrolling with Shift held pans the pager.
+    pub smooth_scroll: bool,
+    /// Mark windows that opened since the pointer last hovered the applet with a dot
+    pub mark_new_windows: bool,
+    /// Briefly pulse the icon of a window that just opened
+    pub animate_new_windows: bool,
+    /// Icon themes tried in order when an icon is missing from the current theme
+    pub icon_theme_fallbacks: Vec<String>,
+    /// Icon theme roots, e.g. a Nix profile's `share/icons`, searched before the standard
+    /// locations
+    pub extra_icon_dirs: Vec<PathBuf>,
+    /// Show each workspace's name before its window icons
+    pub show_workspace_names: bool,
+    /// Whether the shown name is the workspace's name or its position
+    pub label_mode: LabelMode,
+    /// Render a workspace's window icons as an overlapping cluster
+    pub stacked_icons: bool,
+    /// Which window icons each workspace shows, `stacked_icons` applies with `All`
+    pub icon_mode: IconMode,
+    /// Show windows that are on several workspaces, e.g. sticky ones, on each of them rather
+    /// than only on the last one reported
+    pub show_sticky_on_all_workspaces: bool,
+    /// Icon name or absolute path to use for an app id instead of its detected icon
+    pub icon_overrides: HashMap<String, String>,
+    /// Name to show for an app id instead of its desktop entry name, e.g. `org.gnome.Nautilus`
+    /// as "Files"
+    pub app_id_labels: HashMap<String, String>,
+    /// App ids whose windows are never shown, either exact or patterns where `*` matches any
+    /// run of characters, e.g. `org.example.*`
+    pub hidden_app_ids: Vec<String>,
+    /// Maximum number of app icons kept in memory, the least recently shown are dropped first
+    pub icon_cache_capacity: usize,
+    /// Use the monochrome `-symbolic` variant of app icons where the icon theme has one
+    pub prefer_symbolic_icons: bool,
+    /// Draw symbolic icons in the panel's foreground color, for a uniform monochrome look
+    /// together with `prefer_symbolic_icons`
+    pub recolor_icons_to_theme: bool,
+    /// Primary click action of the panel button
+    pub click_action: ClickAction,
+    /// Highlight style of the active workspace
+    pub active_highlight: ActiveHighlight,
+    /// Border width of the active workspace with the border highlight
+    pub active_border_width: f32,
+    /// RGBA color (components in 0.0..=1.0) of the active workspace highlight, the theme
+    /// accent color if unset
+    pub active_color: Option<[f32; 4]>,
+    /// Corner radius of the active workspace highlight
+    pub active_radius: CornerRadius,
+    /// Corner radius of the border and background around window icons, `Xl` for round ones
+    pub icon_radius: CornerRadius,
+    /// Opacity (0.0..=1.0) of the names of inactive workspaces
+    pub inactive_opacity: f32,
+    /// Apply `inactive_opacity` to the window icons of inactive workspaces as well
+    pub dim_inactive_icons: bool,
+    /// Order of the window icons within a workspace
+    pub toplevel_sort: ToplevelSort,
+    /// Show the number of windows next to each workspace's name
+    pub show_window_count: bool,
+    /// Multiplier of the panel spacing between workspaces
+    pub workspace_spacing_scale: f32,
+    /// Multiplier of the panel spacing between a workspace's name and icons
+    pub icon_spacing_scale: f32,
+    /// How windows without an app id are shown
+    pub unidentified_windows: UnidentifiedWindows,
+    /// Icon name or absolute path shown for apps without a resolvable icon, a built-in
+    /// placeholder if unset
+    pub fallback_icon: Option<String>,
+    /// Show the workspaces in reverse order
+    pub reverse_workspace_order: bool,
+    /// Show each workspace's window icons in reverse order, after sorting by `toplevel_sort`
+    pub reverse_icon_order: bool,
+    /// Collapse the applet while no workspace has any windows, including the settings button
+    pub autohide_when_empty: bool,
+    /// Multiplier of the text size derived from the panel size
+    pub text_size_scale: f32,
+    /// Text size in pixels, overriding the derived and scaled size
+    pub text_size_override: Option<u16>,
+    /// Draw a thin divider between adjacent workspaces
+    pub show_separators: bool,
+    /// Highlight color per workspace name or 1-based position, as `#rrggbb` or `#rrggbbaa`,
+    /// used instead of `active_color` for that workspace
+    pub workspace_colors: HashMap<String, String>,
+    /// Leave minimized windows out of the panel, the popups still list them so they can be
+    /// restored
+    pub hide_minimized: bool,
+    /// Leave workspaces without windows out of the panel, except the active ones
+    pub hide_empty_workspaces: bool,
+    /// Workspaces by name or 1-based position that are shown even when empty and
+    /// `hide_empty_workspaces` is on
+    pub pinned_workspaces: Vec<String>,
+    /// Whether workspace groups spanning several outputs are shown on this panel
+    pub group_output_filter: GroupOutputFilter,
+    /// Where windows without a known workspace are shown
+    pub unassigned_windows: UnassignedWindows,
+    /// Show the title of the focused window after the workspaces, cut to `title_max_chars`
+    pub show_active_title: bool,
+    /// Maximum number of characters of a window title before it is cut short, unlimited if
+    /// unset
+    pub title_max_chars: Option<usize>,
+    /// Appended to window titles that were cut short
+    pub title_ellipsis: String,
+    /// Shown while the compositor reports no workspaces, nothing if empty
+    pub empty_placeholder: String,
+    /// Shown until the compositor connection is established, nothing if empty
+    pub connecting_placeholder: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            demo: String::new(),
+            preset: Preset::default(),
+            dim_minimized: true,
+            numbers_mode: false,
+            layout: Layout::default(),
+            show_settings_button: false,
+            workspace_icons: HashMap::new(),
+            statusline_interval_ms: 1000,
+            overflow_threshold: None,
+            active_emphasis: ActiveEmphasis::default(),
+            icon_size_override: None,
+            smooth_scroll: false,
+            mark_new_windows: false,
+            animate_new_windows: false,
+            icon_theme_fallbacks: Vec::new(),
+            extra_icon_dirs: Vec::new(),
+            show_workspace_names: true,
+            label_mode: LabelMode::default(),
+            stacked_icons: false,
+            icon_mode: IconMode::default(),
+            show_sticky_on_all_workspaces: true,
+            icon_overrides: HashMap::new(),
+            app_id_labels: HashMap::new(),
+            hidden_app_ids: Vec::new(),
+            icon_cache_capacity: DEFAULT_CACHE_CAPACITY.get(),
+            prefer_symbolic_icons: false,
+            recolor_icons_to_theme: false,
+            click_action: ClickAction::default(),
+            active_highlight: ActiveHighlight::default(),
+            active_border_width: 2.0,
+            active_color: None,
+            active_radius: CornerRadius::default(),
+            icon_radius: CornerRadius::Xs,
+            inactive_opacity: 0.5,
+            dim_inactive_icons: false,
+            toplevel_sort: ToplevelSort::default(),
+            show_window_count: false,
+            workspace_spacing_scale: 1.0,
+            icon_spacing_scale: 1.0,
+            unidentified_windows: UnidentifiedWindows::default(),
+            fallback_icon: None,
+            reverse_workspace_order: false,
+            reverse_icon_order: false,
+            autohide_when_empty: false,
+            text_size_scale: 1.0,
+            text_size_override: None,
+            show_separators: false,
+            workspace_colors: HashMap::new(),
+            hide_minimized: false,
+            hide_empty_workspaces: false,
+            pinned_workspaces: Vec::new(),
+            group_output_filter: GroupOutputFilter::default(),
+            unassigned_windows: UnassignedWindows::default(),
+            show_active_title: false,
+            title_max_chars: Some(24),
+            title_ellipsis: String::from("…"),
+            empty_placeholder: String::from("..."),
+            connecting_placeholder: String::from("..."),
+        }
+    }
+}
+
+/// Range `icon_size_override` is clamped to.
+const ICON_SIZE_RANGE: std::ops::RangeInclusive<u16> = 8..=128;
+/// Range `active_border_width` is clamped to.
+const BORDER_WIDTH_RANGE: std::ops::RangeInclusive<f32> = 0.0..=8.0;
+/// Range `text_size_override` is clamped to.
+const TEXT_SIZE_RANGE: std::ops::RangeInclusive<u16> = 6..=64;
+/// Range `text_size_scale` is clamped to.
+const TEXT_SCALE_RANGE: std::ops::RangeInclusive<f32> = 0.25..=4.0;
+/// Range the spacing multipliers are clamped to.
+const SPACING_SCALE_RANGE: std::ops::RangeInclusive<f32> = 0.0..=4.0;
+
+impl Config {
+    /// Resets values that are out of range to their defaults, logging each correction.
+    pub fn validate(&mut self) {
+        if let Some(size) = self.icon_size_override {
+            let clamped = size.clamp(*ICON_SIZE_RANGE.start(), *ICON_SIZE_RANGE.end());
+            if clamped != size {
+                log::warn!("icon_size_override={size} clamped to {clamped}");
+                self.icon_size_override = Some(clamped);
+            }
+        }
+        if let Some(size) = self.text_size_override {
+            let clamped = size.clamp(*TEXT_SIZE_RANGE.start(), *TEXT_SIZE_RANGE.end());
+            if clamped != size {
+                log::warn!("text_size_override={size} clamped to {clamped}");
+                self.text_size_override = Some(clamped);
+            }
+        }
+        if self.icon_cache_capacity == 0 {
+            log::warn!("icon_cache_capacity=0 ignored - must be positive");
+            self.icon_cache_capacity = Self::default().icon_cache_capacity;
+        }
+        if self.title_max_chars == Some(0) {
+            log::warn!("title_max_chars=0 ignored - must be positive");
+            self.title_max_chars = Self::default().title_max_chars;
+        }
+        if self.statusline_interval_ms == 0 {
+            log::warn!("statusline_interval_ms=0 ignored - must be positive");
+            self.statusline_interval_ms = Self::default().statusline_interval_ms;
+        }
+        let defaults = Self::default();
+        validate_range(
+            "active_border_width",
+            &mut self.active_border_width,
+            BORDER_WIDTH_RANGE,
+            defaults.active_border_width,
+        );
+        validate_range(
+            "inactive_opacity",
+            &mut self.inactive_opacity,
+            0.0..=1.0,
+            defaults.inactive_opacity,
+        );
+        validate_range(
+            "workspace_spacing_scale",
+            &mut self.workspace_spacing_scale,
+            SPACING_SCALE_RANGE,
+            defaults.workspace_spacing_scale,
+        );
+        validate_range(
+            "text_size_scale",
+            &mut self.text_size_scale,
+            TEXT_SCALE_RANGE,
+            defaults.text_size_scale,
+        );
+        validate_range(
+            "icon_spacing_scale",
+            &mut self.icon_spacing_scale,
+            SPACING_SCALE_RANGE,
+            defaults.icon_spacing_scale,
+        );
+        self.workspace_colors.retain(|workspace, color| {
+            let valid = Color::parse(color).is_some();
+            if !valid {
+                log::warn!("workspace_colors.{workspace}={color} ignored - not a hex color");
+            }
+            valid
+        });
+        validate_rgba("active_color", &mut self.active_color);
+    }
+
+    /// Sets the fields bundled by `preset` unless they are configured to a non-default value,
+    /// so individual settings compose with the preset by overriding it. A field configured to
+    /// its default value can't be told apart from an unset one and takes the preset's value,
+    /// turning off an option the preset turns on requires `Preset::None`.
+    pub fn apply_preset(&mut self) {
+        let defaults = Self::default();
+        match self.preset {
+            Preset::None => {}
+            Preset::Minimal => {
+                apply_preset_value(&mut self.numbers_mode, defaults.numbers_mode, true);
+                apply_preset_value(
+                    &mut self.hide_empty_workspaces,
+                    defaults.hide_empty_workspaces,
+                    true,
+                );
+            }
+            Preset::Full => {
+                apply_preset_value(
+                    &mut self.show_window_count,
+                    defaults.show_window_count,
+                    true,
+                );
+                apply_preset_value(&mut self.show_separators, defaults.show_separators, true);
+                apply_preset_value(&mut self.mark_new_windows, defaults.mark_new_windows, true);
+                apply_preset_value(
+                    &mut self.animate_new_windows,
+                    defaults.animate_new_windows,
+                    true,
+                );
+                apply_preset_value(
+                    &mut self.show_settings_button,
+                    defaults.show_settings_button,
+                    true,
+                );
+            }
+            Preset::ClassicTaskbar => {
+                apply_preset_value(&mut self.layout, defaults.layout, Layout::Flat);
+                apply_preset_value(
+                    &mut self.toplevel_sort,
+                    defaults.toplevel_sort,
+                    ToplevelSort::Stable,
+                );
+            }
+        }
+    }
+
+    /// Whether `app_id` matches any of the `hidden_app_ids` patterns
+    pub fn is_app_hidden(&self, app_id: &str) -> bool {
+        self.hidden_app_ids
+            .iter()
+            .any(|pattern| wildcard_match(pattern, app_id))
+    }
+
+    /// The windows listed per workspace, derived from the reported ones. Sticky windows are
+    /// only listed on their own workspace unless `show_sticky_on_all_workspaces`, hidden app
+    /// ids and, depending on `unidentified_windows`, windows without an app id are left out.
+    /// Each list is sorted by [`Config::sort_toplevels`].
+    pub fn arrange_toplevels(
+        &self,
+        toplevels: &HashMap<ExtWorkspaceHandleV1, HashMap<ExtForeignToplevelHandleV1, AppToplevel>>,
+    ) -> HashMap<ExtWorkspaceHandleV1, Vec<AppToplevel>> {
+        toplevels
+            .iter()
+            .map(|(ws_id, toplevels_by_id)| {
+                let mut toplevels: Vec<AppToplevel> = toplevels_by_id
+                    .values()
+                    .filter(|toplevel| {
+                        self.show_sticky_on_all_workspaces || toplevel.ws_handle == *ws_id
+                    })
+                    .filter(|toplevel| !self.is_app_hidden(&toplevel.app_id))
+                    .filter(|toplevel| {
+                        !toplevel.app_id.is_empty()
+                            || self.unidentified_windows != UnidentifiedWindows::Hide
+                    })
+                    .cloned()
+                    .collect();
+                self.sort_toplevels(&mut toplevels);
+                (ws_id.clone(), toplevels)
+            })
+            .collect()
+    }
+
+    /// Sorts by `toplevel_sort`, reversed if `reverse_icon_order`
+    pub fn sort_toplevels(&self, toplevels: &mut [AppToplevel]) {
+        match self.toplevel_sort {
+            ToplevelSort::Geometry => toplevels.sort_by(AppToplevel::cmp_geometry),
+            ToplevelSort::AppId => {
+                toplevels.sort_by(|a, b| a.app_id.cmp(&b.app_id).then_with(|| a.cmp_sequence(b)))
+            }
+            ToplevelSort::Title => {
+                toplevels.sort_by(|a, b| a.title.cmp(&b.title).then_with(|| a.cmp_sequence(b)))
+            }
+            ToplevelSort::Stable => toplevels.sort_by(AppToplevel::cmp_sequence),
+        }
+        if self.reverse_icon_order {
+            toplevels.reverse();
+        }
+    }
+
+    /// Whether a window listed by [`Config::arrange_toplevels`] is shown in the panel, rather
+    /// than only in the popups, see `hide_minimized`
+    pub fn shows_in_panel(&self, toplevel: &AppToplevel) -> bool {
+        !(self.hide_minimized && toplevel.is_minimized)
+    }
+
+    pub fn active_color(&self) -> Option<Color> {
+        self.active_color
+            .map(|[r, g, b, a]| Color::from_rgba(r, g, b, a))
+    }
+
+    /// `title` cut to `title_max_chars` characters followed by `title_ellipsis` if it is
+    /// longer. Characters are counted as graphemes so emoji and combining marks stay whole.
+    pub fn truncate_title<'a>(&self, title: &'a str) -> Cow<'a, str> {
+        let Some(max_chars) = self.title_max_chars else {
+            return Cow::Borrowed(title);
+        };
+        match title.grapheme_indices(true).nth(max_chars) {
+            Some((end, _)) => Cow::Owned(format!(
+                "{}{}",
+                title[..end].trim_end(),
+                self.title_ellipsis
+            )),
+            None => Cow::Borrowed(title),
+        }
+    }
+
+    /// The label of the workspace named `name` at 0-based `index`, see [`Config::label_mode`]
+    pub fn workspace_label<'a>(&self, name: &'a str, index: usize) -> Cow<'a, str> {
+        match self.label_mode {
+            LabelMode::Name => Cow::Borrowed(name),
+            LabelMode::NameOrIndex if !name.is_empty() => Cow::Borrowed(name),
+            LabelMode::Index | LabelMode::NameOrIndex => Cow::Owned((index + 1).to_string()),
+        }
+    }
+
+    /// Whether a workspace is pinned by name or by its 1-based position
+    pub fn is_workspace_pinned(&self, name: &str, index: usize) -> bool {
+        let position = (index + 1).to_string();
+        self.pinned_workspaces
+            .iter()
+            .any(|pinned| pinned == name || *pinned == position)
+    }
+
+    /// The configured color of a workspace, looked up by name before its 1-based position
+    pub fn workspace_color(&self, name: &str, index: usize) -> Option<Color> {
+        self.workspace_colors
+            .get(name)
+            .or_else(|| self.workspace_colors.get(&(index + 1).to_string()))
+            .and_then(|color| Color::parse(color))
+    }
+}
+
+/// Sets `value` to `preset` unless it differs from `default`.
+fn apply_preset_value<T: PartialEq>(value: &mut T, default: T, preset: T) {
+    if *value == default {
+        *value = preset;
+    }
+}
+
+/// Clamps `value` to `range`, resetting NaN to `default`.
+fn validate_range(key: &str, value: &mut f32, range: std::ops::RangeInclusive<f32>, default: f32) {
+    if !range.contains(value) {
+        let clamped = if value.is_nan() {
+            default
+        } else {
+            value.clamp(*range.start(), *range.end())
+        };
+        log::warn!("{key}={value} clamped to {clamped}");
+        *value = clamped;
+    }
+}
+
+/// Clears a color whose components are outside 0.0..=1.0.
+fn validate_rgba(key: &str, color: &mut Option<[f32; 4]>) {
+    if let Some(rgba) = color {
+        if rgba.iter().any(|c| !(0.0..=1.0).contains(c)) {
+            log::warn!("{key}={rgba:?} ignored - components must be within 0.0..=1.0");
+            *color = None;
+        }
+    }
+}
+
+/// Matches `text` against `pattern`, where each `*` matches any run of characters.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // split always yields at least one part
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts = parts.peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            // The last part is anchored at the end, after a `*`
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    // No `*` at all, so the match has to be exact
+    rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wayland_mock::Handles;
+
+    #[test]
+    fn arranged_toplevels_follow_the_filter_and_sort_settings() {
+        let handles = Handles::new().expect("mock handles");
+        let (ws1, ws2) = (
+            handles.workspace("1", vec![0]),
+            handles.workspace("2", vec![1]),
+        );
+        let toplevel = |app_id: &str, sequence| AppToplevel {
+            sequence,
+            ..handles.toplevel(&ws1, app_id)
+        };
+        let sticky = AppToplevel {
+            ws_handle: ws2.handle.clone(),
+            ws_handles: vec![ws1.handle.clone(), ws2.handle.clone()],
+            ..toplevel("sticky", 5)
+        };
+        let minimized = AppToplevel {
+            is_minimized: true,
+            ..toplevel("minimized", 2)
+        };
+        let raw: HashMap<_, HashMap<_, _>> = HashMap::from([(
+            ws1.handle.clone(),
+            [
+                toplevel("firefox", 1),
+                minimized,
+                toplevel("", 3),
+                toplevel("hidden.app", 4),
+                sticky,
+            ]
+            .into_iter()
+            .map(|toplevel| (toplevel.handle.clone(), toplevel))
+            .collect(),
+        )]);
+        let app_ids = |config: &Config| -> Vec<String> {
+            config.arrange_toplevels(&raw)[&ws1.handle]
+                .iter()
+                .filter(|toplevel| config.shows_in_panel(toplevel))
+                .map(|toplevel| toplevel.app_id.clone())
+                .collect()
+        };
+
+        let mut config = Config {
+            toplevel_sort: ToplevelSort::Stable,
+            ..Config::default()
+        };
+        assert_eq!(
+            app_ids(&config),
+            ["firefox", "minimized", "", "hidden.app", "sticky"]
+        );
+
+        config.hidden_app_ids = vec!["hidden.*".to_string()];
+        config.unidentified_windows = UnidentifiedWindows::Hide;
+        config.show_sticky_on_all_workspaces = false;
+        assert_eq!(app_ids(&config), ["firefox", "minimized"]);
+
+        config.reverse_icon_order = true;
+        assert_eq!(app_ids(&config), ["minimized", "firefox"]);
+
+        config.hide_minimized = true;
+        assert_eq!(app_ids(&config), ["firefox"]);
+    }
 }
@@ -0,0 +1,123 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Optional DBus service exposing the workspaces and windows the applet renders, for scripts.
+//!
+//! The state is published as the JSON form of [`Snapshot`], through the `State` method and
+//! the `StateChanged` signal.
+
+use std::sync::Arc;
+
+use cosmic::iced;
+use futures_util::StreamExt;
+use tokio::sync::watch;
+use zbus::object_server::SignalEmitter;
+
+use crate::snapshot::Snapshot;
+
+const BUS_NAME: &str = "io.github.mlu1109.CosmicAppletWorkspaceApps";
+const OBJECT_PATH: &str = "/io/github/mlu1109/CosmicAppletWorkspaceApps";
+
+/// Hands the latest state to the DBus service task.
+#[derive(Clone)]
+pub struct StatePublisher(Arc<watch::Sender<Snapshot>>);
+
+impl StatePublisher {
+    /// Publishes `snapshot` unless it equals the state published last.
+    pub fn publish(&self, snapshot: Snapshot) {
+        self.0.send_if_modified(|current| {
+            if *current == snapshot {
+                return false;
+            }
+            *current = snapshot;
+            true
+        });
+    }
+}
+
+impl std::fmt::Debug for StatePublisher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StatePublisher").finish_non_exhaustive()
+    }
+}
+
+struct Service {
+    /// JSON of the last published snapshot
+    state: String,
+}
+
+#[zbus::interface(name = "io.github.mlu1109.CosmicAppletWorkspaceApps")]
+impl Service {
+    /// The workspaces with their windows as JSON
+    fn state(&self) -> String {
+        self.state.clone()
+    }
+
+    #[zbus(signal)]
+    async fn state_changed(emitter: &SignalEmitter<'_>, state: &str) -> zbus::Result<()>;
+}
+
+/// Creates an iced Subscription that starts the DBus service and emits a [`StatePublisher`]
+/// once the bus name is owned. The service runs on its own task until the publisher is
+/// dropped.
+pub fn subscription() -> iced::Subscription<StatePublisher> {
+    iced::Subscription::run_with_id(
+        "dbus-service-sub",
+        futures_util::stream::once(async {
+            let (sender, receiver) = watch::channel(Snapshot::default());
+            let connection = zbus::connection::Builder::session()
+                .and_then(|builder| builder.name(BUS_NAME))
+                .and_then(|builder| {
+                    builder.serve_at(
+                        OBJECT_PATH,
+                        Service {
+                            state: to_json(&receiver.borrow()),
+                        },
+                    )
+                });
+            let connection = match connection {
+                Ok(builder) => builder.build().await,
+                Err(err) => Err(err),
+            };
+            match connection {
+                Ok(connection) => {
+                    tokio::spawn(serve(connection, receiver));
+                    Some(StatePublisher(Arc::new(sender)))
+                }
+                Err(err) => {
+                    log::warn!("dbus service unavailable: {err}");
+                    None
+                }
+            }
+        })
+        .filter_map(std::future::ready),
+    )
+}
+
+/// Forwards each published state to the interface and emits `StateChanged`.
+async fn serve(connection: zbus::Connection, mut receiver: watch::Receiver<Snapshot>) {
+    let service = match connection
+        .object_server()
+        .interface::<_, Service>(OBJECT_PATH)
+        .await
+    {
+        Ok(service) => service,
+        Err(err) => {
+            log::warn!("dbus service stopped - interface not found: {err}");
+            return;
+        }
+    };
+    while receiver.changed().await.is_ok() {
+        let state = to_json(&receiver.borrow_and_update());
+        service.get_mut().await.state = state.clone();
+        if let Err(err) = Service::state_changed(service.signal_emitter(), &state).await {
+            log::warn!("failed to emit StateChanged: {err}");
+        }
+    }
+}
+
+fn to_json(snapshot: &Snapshot) -> String {
+    serde_json::to_string(snapshot).unwrap_or_else(|err| {
+        log::warn!("failed to serialize state: {err}");
+        String::new()
+    })
+}
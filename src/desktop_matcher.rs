@@ -1,39 +1,200 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+
+/// `Name[...]` keys to try for the current locale, most specific first
+static LOCALE_KEYS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    DesktopMatcher::locale_keys(&locale)
+});
 
 /// Represents a parsed desktop entry with relevant fields
 #[derive(Debug, Clone)]
 pub struct DesktopEntry {
     pub path: PathBuf,
+    /// Name value localized for the current locale, falling back to the unlocalized Name
+    pub name: Option<String>,
+    /// StartupWMClass value, some entries list several classes separated by `;`
     pub startup_wm_class: Option<String>,
     pub icon: Option<String>,
+    /// Exec value, the command line launching the application
+    pub exec: Option<String>,
+    /// NoDisplay=true, the entry is valid but not meant to be shown in menus
+    pub no_display: bool,
+    /// Hidden=true, the entry is treated as deleted
+    pub hidden: bool,
+    /// Terminal=true, the program has to be run in a terminal
+    pub terminal: bool,
+}
+
+impl DesktopEntry {
+    /// The StartupWMClass tokens, split on `;`
+    pub fn wm_classes(&self) -> impl Iterator<Item = &str> {
+        self.startup_wm_class
+            .iter()
+            .flat_map(|classes| classes.split(';'))
+            .map(str::trim)
+            .filter(|class| !class.is_empty())
+    }
+
+    /// Basename of the program in the Exec line, e.g. "firefox" for "/usr/bin/firefox %u"
+    ///
+    /// Arguments are split following the desktop entry spec quoting rules, and a leading
+    /// `env` with its `NAME=value` assignments is skipped.
+    pub fn exec_basename(&self) -> Option<String> {
+        let args = Self::split_exec(self.exec.as_deref()?);
+        let mut args = args.iter().map(String::as_str).peekable();
+        if args
+            .peek()
+            .is_some_and(|arg| arg == "env" || arg.ends_with("/env"))
+        {
+            args.next();
+            while args.next_if(|arg| arg.contains('=')).is_some() {}
+        }
+        let program = args.find(|arg| !arg.starts_with('%'))?;
+        program
+            .rsplit('/')
+            .next()
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+    }
+
+    /// The Exec line as program and arguments, with the field codes expanded as far as they
+    /// apply to launching without files or URLs
+    pub fn launch_args(&self) -> Option<Vec<String>> {
+        let mut args = Vec::new();
+        for arg in Self::split_exec(self.exec.as_deref()?) {
+            match arg.as_str() {
+                "%i" => {
+                    if let Some(icon) = &self.icon {
+                        args.extend(["--icon".to_string(), icon.clone()]);
+                    }
+                }
+                "%c" => args.extend(self.name.clone()),
+                "%k" => args.push(self.path.to_string_lossy().into_owned()),
+                _ => {
+                    let arg = Self::strip_field_codes(&arg);
+                    // A lone file or URL code like %U expands to nothing
+                    if !arg.is_empty() {
+                        args.push(arg);
+                    }
+                }
+            }
+        }
+        (!args.is_empty()).then_some(args)
+    }
+
+    /// Removes the field codes from an argument, `%%` becomes a literal `%`
+    fn strip_field_codes(arg: &str) -> String {
+        let mut stripped = String::new();
+        let mut chars = arg.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                stripped.push(c);
+            } else if chars.next() == Some('%') {
+                stripped.push('%');
+            }
+        }
+        stripped
+    }
+
+    /// Splits an Exec value into arguments. Arguments may be double quoted, in which case
+    /// a backslash escapes the next character.
+    fn split_exec(exec: &str) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut current = String::new();
+        let mut in_arg = false;
+        let mut quoted = false;
+        let mut chars = exec.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {
+                    quoted = !quoted;
+                    in_arg = true;
+                }
+                '\\' if quoted => current.extend(chars.next()),
+                c if c.is_whitespace() && !quoted => {
+                    if in_arg {
+                        args.push(std::mem::take(&mut current));
+                        in_arg = false;
+                    }
+                }
+                c => {
+                    current.push(c);
+                    in_arg = true;
+                }
+            }
+        }
+        if in_arg {
+            args.push(current);
+        }
+        args
+    }
 }
 
 /// Desktop file matcher that searches for .desktop files matching an app ID
+#[derive(Debug, Clone)]
 pub struct DesktopMatcher {
+    /// Indexed entries in the order they were indexed, the indexes are rebuilt from these
+    /// when desktop files change so entries that lost a key to a changed one get it back
+    entries: Vec<DesktopEntry>,
     /// Cache of desktop entries indexed by lowercase filename (without .desktop extension)
     filename_index: HashMap<String, DesktopEntry>,
+    /// Lowercase app IDs that matched no entry, cleared whenever the index changes
+    misses: MissCache,
     /// Cache of desktop entries indexed by lowercase StartupWMClass
     wm_class_index: HashMap<String, DesktopEntry>,
+    /// Cache of desktop entries indexed by the last dot-separated segment of
+    /// reverse-DNS filenames (e.g. "code" for com.visualstudio.code.desktop)
+    segment_index: HashMap<String, DesktopEntry>,
+    /// Cache of desktop entries indexed by the lowercase basename of their Exec program
+    exec_index: HashMap<String, DesktopEntry>,
 }
 
 impl DesktopMatcher {
     /// Create a new desktop matcher by scanning XDG data directories
     pub fn new() -> Self {
-        let mut matcher = Self {
+        if Self::get_xdg_data_home().is_none() {
+            log::warn!(
+                "neither XDG_DATA_HOME nor HOME is set - user desktop entries are not found"
+            );
+        }
+        Self::from_data_dirs(&Self::get_xdg_data_dirs())
+    }
+
+    /// Create a matcher from the desktop files of `data_dirs`, in precedence order
+    pub(crate) fn from_data_dirs(data_dirs: &[String]) -> Self {
+        let mut matcher = Self::empty();
+        matcher.scan_directories(data_dirs);
+        matcher
+    }
+
+    /// A matcher without any entries, filled through [`DesktopMatcher::index_entry`]
+    fn empty() -> Self {
+        Self {
+            entries: Vec::new(),
             filename_index: HashMap::new(),
             wm_class_index: HashMap::new(),
-        };
-        matcher.scan_directories();
-        matcher
+            segment_index: HashMap::new(),
+            exec_index: HashMap::new(),
+            misses: MissCache::default(),
+        }
     }
 
-    /// Scan XDG data directories for desktop files
-    fn scan_directories(&mut self) {
-        let data_dirs = Self::get_xdg_data_dirs();
-        
+    /// Scan the `applications` directories of data directories for desktop files
+    ///
+    /// `data_dirs` are in precedence order, see [`DesktopMatcher::get_xdg_data_dirs`]. A
+    /// desktop file shadows files with the same name in later directories entirely, so a user
+    /// override also replaces the StartupWMClass and Exec matches of the system file.
+    fn scan_directories(&mut self, data_dirs: &[String]) {
+        let mut seen_ids = HashSet::new();
+
         for data_dir in data_dirs {
             let apps_dir = Path::new(&data_dir).join("applications");
             if !apps_dir.exists() {
@@ -43,10 +204,14 @@ impl DesktopMatcher {
             if let Ok(entries) = fs::read_dir(&apps_dir) {
                 for entry in entries.flatten() {
                     let path = entry.path();
-                    if path.extension().and_then(|s| s.to_str()) == Some("desktop") {
-                        if let Some(entry) = Self::parse_desktop_file(&path) {
-                            self.index_entry(entry);
-                        }
+                    if path.extension().and_then(|s| s.to_str()) != Some("desktop") {
+                        continue;
+                    }
+                    if !seen_ids.insert(entry.file_name()) {
+                        continue;
+                    }
+                    if let Some(entry) = Self::parse_desktop_file(&path) {
+                        self.index_entry(entry);
                     }
                 }
             }
@@ -54,34 +219,140 @@ impl DesktopMatcher {
     }
 
     /// Index a desktop entry for fast lookup (case-insensitive)
+    ///
+    /// Hidden entries are skipped. Otherwise the first entry wins, except that a
+    /// visible entry replaces a NoDisplay one.
     pub fn index_entry(&mut self, entry: DesktopEntry) {
+        if entry.hidden {
+            return;
+        }
+        self.misses.clear();
+        self.index_keys(&entry);
+        self.entries.push(entry);
+    }
+
+    fn index_keys(&mut self, entry: &DesktopEntry) {
         if let Some(filename) = entry.path.file_stem().and_then(|s| s.to_str()) {
-            // Index by lowercase filename (first one wins)
-            self.filename_index
-                .entry(filename.to_lowercase())
-                .or_insert_with(|| entry.clone());
-            
-            // Index by lowercase StartupWMClass if present
-            if let Some(ref wm_class) = entry.startup_wm_class {
-                self.wm_class_index
-                    .entry(wm_class.to_lowercase())
-                    .or_insert(entry);
+            // Index by lowercase filename
+            Self::insert_entry(&mut self.filename_index, filename.to_lowercase(), &entry);
+
+            // Index reverse-DNS filenames by their last segment
+            if let Some((_, segment)) = filename.rsplit_once('.') {
+                Self::insert_entry(&mut self.segment_index, segment.to_lowercase(), entry);
+            }
+
+            // Index by each lowercase StartupWMClass token if present
+            for wm_class in entry.wm_classes() {
+                Self::insert_entry(&mut self.wm_class_index, wm_class.to_lowercase(), entry);
             }
+
+            // Index by the program the entry launches
+            if let Some(program) = entry.exec_basename() {
+                Self::insert_entry(&mut self.exec_index, program.to_lowercase(), entry);
+            }
+        }
+    }
+
+    /// Insert unless the key is taken, letting a visible entry replace a NoDisplay one
+    fn insert_entry(index: &mut HashMap<String, DesktopEntry>, key: String, entry: &DesktopEntry) {
+        let replace = index
+            .get(&key)
+            .is_none_or(|existing| existing.no_display && !entry.no_display);
+        if replace {
+            index.insert(key, entry.clone());
+        }
+    }
+
+    /// Re-parse the given desktop files after they were created, modified or deleted
+    ///
+    /// The file with the same name and the highest precedence replaces the indexed one, so
+    /// deleting a user override brings back the system file it shadowed. The indexes are then
+    /// rebuilt, giving keys the changed files no longer claim to the entries next in line.
+    pub fn reindex_paths(&mut self, paths: &[PathBuf]) {
+        self.reindex_paths_in(paths, &Self::get_xdg_data_dirs());
+    }
+
+    fn reindex_paths_in(&mut self, paths: &[PathBuf], data_dirs: &[String]) {
+        self.misses.clear();
+        for path in paths {
+            let Some(file_name) = path.file_name() else {
+                continue;
+            };
+            let indexed = self
+                .entries
+                .iter()
+                .position(|entry| entry.path.file_name() == Some(file_name));
+            let winner = data_dirs
+                .iter()
+                .map(|data_dir| Path::new(data_dir).join("applications").join(file_name))
+                .find(|candidate| candidate.exists())
+                .and_then(|winner| Self::parse_desktop_file(&winner))
+                .filter(|entry| !entry.hidden);
+            match (indexed, winner) {
+                // Keeps its place, an edited file doesn't lose keys it shares
+                (Some(index), Some(entry)) => self.entries[index] = entry,
+                (Some(index), None) => {
+                    self.entries.remove(index);
+                }
+                (None, Some(entry)) => self.entries.push(entry),
+                (None, None) => {}
+            }
+        }
+
+        self.filename_index.clear();
+        self.wm_class_index.clear();
+        self.segment_index.clear();
+        self.exec_index.clear();
+        let entries = std::mem::take(&mut self.entries);
+        for entry in &entries {
+            self.index_keys(entry);
         }
+        self.entries = entries;
     }
 
     /// Parse a desktop file and extract relevant fields
     pub fn parse_desktop_file(path: &Path) -> Option<DesktopEntry> {
-        let file = fs::File::open(path).ok()?;
-        let reader = BufReader::new(file);
-        
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(err) => {
+                log::debug!("{} not indexed - failed to open: {err}", path.display());
+                return None;
+            }
+        };
+        Some(Self::parse_desktop_entry(
+            path,
+            BufReader::new(file),
+            &LOCALE_KEYS,
+        ))
+    }
+
+    /// Parse the contents of the desktop file at `path`, picking the `Name[...]` of the first
+    /// of `locale_keys` that has one, see [`DesktopMatcher::locale_keys`]
+    fn parse_desktop_entry(
+        path: &Path,
+        reader: impl BufRead,
+        locale_keys: &[String],
+    ) -> DesktopEntry {
         let mut in_desktop_entry = false;
+        let mut name = None;
+        let mut localized_names = HashMap::new();
         let mut startup_wm_class = None;
         let mut icon = None;
-        
-        for line in reader.lines().flatten() {
+        let mut exec = None;
+        let mut no_display = false;
+        let mut hidden = false;
+        let mut terminal = false;
+
+        for (number, line) in reader.lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => {
+                    log::debug!("{}:{} skipped - {err}", path.display(), number + 1);
+                    continue;
+                }
+            };
             let line = line.trim();
-            
+
             // Check if we're in the [Desktop Entry] section
             if line == "[Desktop Entry]" {
                 in_desktop_entry = true;
@@ -90,72 +361,663 @@ impl DesktopMatcher {
                 in_desktop_entry = false;
                 continue;
             }
-            
+
             if !in_desktop_entry {
                 continue;
             }
-            
+
             // Parse key=value pairs
             if let Some((key, value)) = line.split_once('=') {
                 match key.trim() {
+                    "Name" => name = Some(value.trim().to_string()),
+                    key if key.starts_with("Name[") && key.ends_with(']') => {
+                        let locale = &key["Name[".len()..key.len() - 1];
+                        localized_names.insert(locale.to_string(), value.trim().to_string());
+                    }
                     "StartupWMClass" => startup_wm_class = Some(value.trim().to_string()),
                     "Icon" => icon = Some(value.trim().to_string()),
+                    "Exec" => exec = Some(value.trim().to_string()),
+                    "NoDisplay" => no_display = value.trim() == "true",
+                    "Hidden" => hidden = value.trim() == "true",
+                    "Terminal" => terminal = value.trim() == "true",
                     _ => {}
                 }
+            } else if !line.is_empty() && !line.starts_with('#') {
+                log::debug!(
+                    "{}:{} skipped - not a key=value pair",
+                    path.display(),
+                    number + 1
+                );
             }
         }
-        
-        Some(DesktopEntry {
+
+        let name = locale_keys
+            .iter()
+            .find_map(|locale| localized_names.remove(locale))
+            .or(name);
+
+        DesktopEntry {
             path: path.to_path_buf(),
+            name,
             startup_wm_class,
             icon,
-        })
+            exec,
+            no_display,
+            hidden,
+            terminal,
+        }
+    }
+
+    /// Locale keys to match against `Name[...]` for a POSIX locale such as `sv_SE.UTF-8@euro`
+    ///
+    /// Follows the desktop entry spec order: `lang_COUNTRY@MODIFIER`, `lang_COUNTRY`,
+    /// `lang@MODIFIER`, `lang`.
+    fn locale_keys(locale: &str) -> Vec<String> {
+        let (locale, modifier) = match locale.split_once('@') {
+            Some((locale, modifier)) => (locale, Some(modifier)),
+            None => (locale, None),
+        };
+        let locale = locale
+            .split_once('.')
+            .map_or(locale, |(locale, _encoding)| locale);
+        if locale.is_empty() || locale == "C" || locale == "POSIX" {
+            return Vec::new();
+        }
+        let (lang, country) = match locale.split_once('_') {
+            Some((lang, country)) => (lang, Some(country)),
+            None => (locale, None),
+        };
+
+        let mut keys = Vec::new();
+        if let (Some(country), Some(modifier)) = (country, modifier) {
+            keys.push(format!("{lang}_{country}@{modifier}"));
+        }
+        if let Some(country) = country {
+            keys.push(format!("{lang}_{country}"));
+        }
+        if let Some(modifier) = modifier {
+            keys.push(format!("{lang}@{modifier}"));
+        }
+        keys.push(lang.to_string());
+        keys
     }
 
     /// Find a desktop file matching the given app ID (case-insensitive)
-    /// 
+    ///
     /// Tries multiple strategies in order:
     /// 1. Filename match
     /// 2. StartupWMClass match
+    /// 3. Filename/StartupWMClass match of the app ID without a `.desktop` suffix,
+    ///    without a trailing instance number, or as a Snap `name_name` filename
+    /// 4. Filename/StartupWMClass match of the last segment of a reverse-DNS app ID, or of
+    ///    the app ID without a `.bin` or architecture suffix
+    /// 5. Reverse-DNS filename whose last segment is the app ID
+    /// 6. Exec program basename match of the app ID or of the alternatives from 3 and 4
+    ///
+    /// App IDs without a match are remembered until the index changes, so they aren't
+    /// searched again on every lookup.
     pub fn find_desktop_file(&self, app_id: &str) -> Option<&DesktopEntry> {
         let app_id_lower = app_id.to_lowercase();
-        
-        if let Some(entry) = self.filename_index.get(&app_id_lower) {
+        if self.misses.contains(&app_id_lower) {
+            return None;
+        }
+        let entry = self.search(&app_id_lower);
+        if entry.is_none() {
+            self.misses.insert(app_id_lower);
+        }
+        entry
+    }
+
+    /// Runs the strategies of [`DesktopMatcher::find_desktop_file`]
+    fn search(&self, app_id_lower: &str) -> Option<&DesktopEntry> {
+        if let Some(entry) = self.find_by_name(app_id_lower) {
+            return Some(entry);
+        }
+
+        for candidate in Self::candidate_names(app_id_lower) {
+            if let Some(entry) = self.find_by_name(&candidate) {
+                return Some(entry);
+            }
+        }
+
+        if let Some(entry) = self.segment_index.get(app_id_lower) {
             return Some(entry);
         }
-        
-        if let Some(entry) = self.wm_class_index.get(&app_id_lower) {
+
+        // Apps reporting their binary name, with a desktop file named differently
+        if let Some(entry) = self.exec_index.get(app_id_lower) {
             return Some(entry);
         }
-        
-        None
+        Self::candidate_names(app_id_lower)
+            .iter()
+            .find_map(|candidate| self.exec_index.get(candidate))
+    }
+
+    /// Describes the outcome of each strategy of [`DesktopMatcher::find_desktop_file`] for
+    /// `app_id`, one per line, to troubleshoot missing icons.
+    pub fn explain(&self, app_id: &str) -> String {
+        let app_id_lower = app_id.to_lowercase();
+        let candidates = Self::candidate_names(&app_id_lower);
+        let describe = |entry: Option<&DesktopEntry>| {
+            entry.map_or_else(
+                || String::from("no match"),
+                |entry| entry.path.display().to_string(),
+            )
+        };
+
+        let mut lines = vec![format!("app_id={app_id}")];
+        let entry = self.filename_index.get(&app_id_lower);
+        lines.push(format!("filename {app_id_lower}: {}", describe(entry)));
+        let entry = self.wm_class_index.get(&app_id_lower);
+        lines.push(format!(
+            "StartupWMClass {app_id_lower}: {}",
+            describe(entry)
+        ));
+        for candidate in &candidates {
+            let entry = self.find_by_name(candidate);
+            lines.push(format!(
+                "filename or StartupWMClass {candidate}: {}",
+                describe(entry)
+            ));
+        }
+        let entry = self.segment_index.get(&app_id_lower);
+        lines.push(format!(
+            "reverse-DNS segment {app_id_lower}: {}",
+            describe(entry)
+        ));
+        for name in std::iter::once(&app_id_lower).chain(&candidates) {
+            lines.push(format!(
+                "Exec {name}: {}",
+                describe(self.exec_index.get(name))
+            ));
+        }
+        lines.push(match self.find_desktop_file(app_id) {
+            Some(entry) => format!(
+                "result: {}, Name={:?}, Icon={:?}",
+                entry.path.display(),
+                entry.name,
+                entry.icon
+            ),
+            None => String::from("result: no desktop file matched"),
+        });
+        lines.join("\n")
+    }
+
+    /// Look up a lowercase name by filename, then by StartupWMClass
+    fn find_by_name(&self, name: &str) -> Option<&DesktopEntry> {
+        self.filename_index
+            .get(name)
+            .or_else(|| self.wm_class_index.get(name))
+    }
+
+    /// Alternative names to try for app IDs that don't match a desktop file directly,
+    /// such as Flatpak, Snap and multi-instance IDs
+    fn candidate_names(app_id_lower: &str) -> Vec<String> {
+        let mut candidates = Vec::new();
+
+        let base = app_id_lower
+            .strip_suffix(".desktop")
+            .unwrap_or(app_id_lower);
+        candidates.push(base.to_string());
+
+        // Instance suffixes like "-2", "_2" or ".2"
+        if let Some(pos) = base.rfind(['-', '_', '.']) {
+            let (stem, suffix) = (&base[..pos], &base[pos + 1..]);
+            if !stem.is_empty() && !suffix.is_empty() && suffix.chars().all(|c| c.is_ascii_digit())
+            {
+                candidates.push(stem.to_string());
+            }
+        }
+
+        // Snap desktop files are named "<snap>_<app>.desktop"
+        candidates.push(format!("{base}_{base}"));
+
+        // Binary name decorations like "foo.bin" or "foo-x86_64"
+        if let Some(stem) = Self::strip_binary_suffix(base) {
+            candidates.push(stem.to_string());
+        }
+
+        // Last segment of a reverse-DNS ID, e.g. "code" for "com.visualstudio.code"
+        if let Some((_, segment)) = base.rsplit_once('.') {
+            if !segment.is_empty() {
+                candidates.push(segment.to_string());
+            }
+        }
+
+        candidates.dedup();
+        candidates.retain(|candidate| candidate != app_id_lower);
+        candidates
     }
 
+    /// Strips a `.bin` or architecture suffix that some programs carry in their app ID
+    fn strip_binary_suffix(name: &str) -> Option<&str> {
+        const SUFFIXES: [&str; 9] = [
+            "bin", "x86_64", "x86-64", "amd64", "aarch64", "arm64", "x64", "i686", "i386",
+        ];
+        SUFFIXES.iter().find_map(|suffix| {
+            let stem = name.strip_suffix(suffix)?;
+            let stem = stem.strip_suffix(['.', '-', '_'])?;
+            (!stem.is_empty()).then_some(stem)
+        })
+    }
+
+    /// Data directories in precedence order: `XDG_DATA_HOME`, `XDG_DATA_DIRS`, then the
+    /// colon-separated [`EXTRA_DATA_DIRS_VAR`] for setups whose directories aren't listed in
+    /// `XDG_DATA_DIRS`, e.g. some Nix profiles, and last the Flatpak exports if missing.
     pub fn get_xdg_data_dirs() -> Vec<String> {
         let mut dirs = Vec::new();
-        
+
         if let Some(data_home) = Self::get_xdg_data_home() {
             dirs.push(data_home);
         }
-        
-        let data_dirs = std::env::var("XDG_DATA_DIRS")
-            .unwrap_or_else(|_| "/usr/local/share:/usr/share".to_string());
-        dirs.extend(data_dirs.split(':').map(String::from));
-        
+
+        let data_dirs = non_empty_var("XDG_DATA_DIRS")
+            .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+        dirs.extend(
+            data_dirs
+                .split(':')
+                .filter(|dir| !dir.is_empty())
+                .map(String::from),
+        );
+        if let Some(extra_dirs) = non_empty_var(EXTRA_DATA_DIRS_VAR) {
+            dirs.extend(
+                extra_dirs
+                    .split(':')
+                    .filter(|dir| !dir.is_empty())
+                    .map(String::from),
+            );
+        }
+        for dir in Self::flatpak_export_dirs() {
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+
+        dirs
+    }
+
+    /// Data directories Flatpak exports the desktop files and icons of installed apps to,
+    /// which are often missing from `XDG_DATA_DIRS` outside of a login shell
+    pub fn flatpak_export_dirs() -> Vec<String> {
+        let mut dirs: Vec<String> = Self::get_xdg_data_home()
+            .map(|data_home| format!("{data_home}/flatpak/exports/share"))
+            .into_iter()
+            .collect();
+        dirs.push(String::from("/var/lib/flatpak/exports/share"));
         dirs
     }
 
     fn get_xdg_data_home() -> Option<String> {
-        std::env::var("XDG_DATA_HOME").ok().or_else(|| {
-            std::env::var("HOME")
-                .ok()
-                .map(|home| format!("{}/.local/share", home))
-        })
+        non_empty_var("XDG_DATA_HOME")
+            .or_else(|| non_empty_var("HOME").map(|home| format!("{}/.local/share", home)))
+    }
+}
+
+/// App IDs without a matching desktop entry. Lookups take `&self` and run on the UI thread
+/// as well as on background threads, hence the Mutex.
+#[derive(Debug, Default)]
+struct MissCache(Mutex<HashSet<String>>);
+
+impl MissCache {
+    fn contains(&self, app_id: &str) -> bool {
+        self.0.lock().is_ok_and(|misses| misses.contains(app_id))
+    }
+
+    fn insert(&self, app_id: String) {
+        if let Ok(mut misses) = self.0.lock() {
+            misses.insert(app_id);
+        }
+    }
+
+    fn clear(&mut self) {
+        if let Ok(misses) = self.0.get_mut() {
+            misses.clear();
+        }
     }
 }
 
+impl Clone for MissCache {
+    fn clone(&self) -> Self {
+        let misses = self
+            .0
+            .lock()
+            .map(|misses| misses.clone())
+            .unwrap_or_default();
+        Self(Mutex::new(misses))
+    }
+}
+
+/// Command line flag printing how the app id that follows it is matched, see
+/// [`DesktopMatcher::explain`].
+pub const EXPLAIN_FLAG: &str = "--explain-app-id";
+
+/// Environment variable listing additional data directories, see
+/// [`DesktopMatcher::get_xdg_data_dirs`]
+pub const EXTRA_DATA_DIRS_VAR: &str = "WORKSPACE_APPS_EXTRA_DATA_DIRS";
+
+/// The value of an environment variable, unless it is unset or empty, which the XDG base
+/// directory spec treats alike
+fn non_empty_var(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|value| !value.is_empty())
+}
+
 impl Default for DesktopMatcher {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn entry(file_name: &str, startup_wm_class: Option<&str>, exec: Option<&str>) -> DesktopEntry {
+        DesktopEntry {
+            path: PathBuf::from("/usr/share/applications").join(file_name),
+            name: None,
+            startup_wm_class: startup_wm_class.map(String::from),
+            icon: None,
+            exec: exec.map(String::from),
+            no_display: false,
+            hidden: false,
+            terminal: false,
+        }
+    }
+
+    fn matched_file(matcher: &DesktopMatcher, app_id: &str) -> Option<String> {
+        let entry = matcher.find_desktop_file(app_id)?;
+        entry.path.file_name()?.to_str().map(String::from)
+    }
+
+    #[test]
+    fn matches_flatpak_snap_and_native_app_ids() {
+        let mut matcher = DesktopMatcher::empty();
+        for entry in [
+            // Flatpak, named by the reverse-DNS app id
+            entry(
+                "org.mozilla.firefox.desktop",
+                None,
+                Some("/app/bin/firefox %u"),
+            ),
+            entry(
+                "org.gnome.Nautilus.desktop",
+                None,
+                Some("nautilus --new-window %U"),
+            ),
+            entry(
+                "com.visualstudio.code.desktop",
+                None,
+                Some("code --unity-launch %F"),
+            ),
+            entry("org.gimp.GIMP.desktop", Some("gimp"), Some("gimp-2.10 %U")),
+            // Snap, named "<snap>_<app>"
+            entry(
+                "snap-store_snap-store.desktop",
+                None,
+                Some("/snap/bin/snap-store"),
+            ),
+            entry(
+                "spotify_spotify.desktop",
+                Some("spotify"),
+                Some("env BAMF=1 spotify %U"),
+            ),
+            // Native
+            entry("gedit.desktop", None, Some("gedit %U")),
+            entry(
+                "idea.desktop",
+                Some("jetbrains-idea"),
+                Some("/opt/idea/bin/idea.sh %f"),
+            ),
+            entry(
+                "libreoffice-writer.desktop",
+                None,
+                Some("libreoffice --writer %U"),
+            ),
+            entry("steam.desktop", None, Some("/usr/bin/steam %U")),
+        ] {
+            matcher.index_entry(entry);
+        }
+
+        let cases = [
+            // Flatpak
+            ("org.mozilla.firefox", Some("org.mozilla.firefox.desktop")),
+            ("org.gnome.Nautilus", Some("org.gnome.Nautilus.desktop")),
+            (
+                "org.gnome.nautilus.desktop",
+                Some("org.gnome.Nautilus.desktop"),
+            ),
+            ("code", Some("com.visualstudio.code.desktop")),
+            ("gimp", Some("org.gimp.GIMP.desktop")),
+            ("gimp-2.10", Some("org.gimp.GIMP.desktop")),
+            // Snap
+            ("snap-store", Some("snap-store_snap-store.desktop")),
+            ("Spotify", Some("spotify_spotify.desktop")),
+            // Native
+            ("gedit", Some("gedit.desktop")),
+            ("gedit-2", Some("gedit.desktop")),
+            ("org.gnome.gedit", Some("gedit.desktop")),
+            ("jetbrains-idea", Some("idea.desktop")),
+            ("libreoffice", Some("libreoffice-writer.desktop")),
+            ("steam.bin", Some("steam.desktop")),
+            ("steam-x86_64", Some("steam.desktop")),
+            ("unknown-app", None),
+        ];
+        for (app_id, expected) in cases {
+            let expected = expected.map(String::from);
+            assert_eq!(matched_file(&matcher, app_id), expected, "app_id={app_id}");
+        }
+    }
+
+    #[test]
+    fn visible_entry_replaces_no_display_one() {
+        let mut matcher = DesktopMatcher::empty();
+        let hidden_class = DesktopEntry {
+            no_display: true,
+            ..entry("foo-helper.desktop", Some("foo"), None)
+        };
+        matcher.index_entry(hidden_class);
+        assert_eq!(
+            matched_file(&matcher, "foo"),
+            Some("foo-helper.desktop".into())
+        );
+        matcher.index_entry(entry("foo-main.desktop", Some("foo"), None));
+        assert_eq!(
+            matched_file(&matcher, "foo"),
+            Some("foo-main.desktop".into())
+        );
+
+        matcher.index_entry(DesktopEntry {
+            hidden: true,
+            ..entry("bar.desktop", None, None)
+        });
+        assert_eq!(matched_file(&matcher, "bar"), None);
+    }
+
+    #[test]
+    fn strip_binary_suffix_removes_separated_suffixes() {
+        let cases = [
+            ("foo.bin", Some("foo")),
+            ("foo-bin", Some("foo")),
+            ("foo_x86_64", Some("foo")),
+            ("foo-x86-64", Some("foo")),
+            ("foo.amd64", Some("foo")),
+            ("foo-aarch64", Some("foo")),
+            ("foo_arm64", Some("foo")),
+            ("foo-x64", Some("foo")),
+            ("foo.i686", Some("foo")),
+            ("foo-i386", Some("foo")),
+            ("foo-bar.bin", Some("foo-bar")),
+            ("foobin", None),
+            ("bin", None),
+            (".bin", None),
+            ("foo", None),
+            ("foo.bin.desktop", None),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(
+                DesktopMatcher::strip_binary_suffix(name),
+                expected,
+                "name={name}"
+            );
+        }
+    }
+
+    #[test]
+    fn candidate_names_cover_instance_snap_binary_and_reverse_dns_ids() {
+        let cases: [(&str, &[&str]); 7] = [
+            ("firefox", &["firefox_firefox"]),
+            ("firefox.desktop", &["firefox", "firefox_firefox"]),
+            ("gedit-2", &["gedit", "gedit-2_gedit-2"]),
+            ("steam.bin", &["steam.bin_steam.bin", "steam", "bin"]),
+            ("foo-x86_64", &["foo-x86", "foo-x86_64_foo-x86_64", "foo"]),
+            (
+                "org.gnome.gedit",
+                &["org.gnome.gedit_org.gnome.gedit", "gedit"],
+            ),
+            ("app.2", &["app", "app.2_app.2", "2"]),
+        ];
+        for (app_id, expected) in cases {
+            assert_eq!(
+                DesktopMatcher::candidate_names(app_id),
+                expected,
+                "app_id={app_id}"
+            );
+        }
+    }
+
+    #[test]
+    fn wm_classes_are_split_on_semicolons() {
+        let cases: [(Option<&str>, &[&str]); 5] = [
+            (None, &[]),
+            (Some(""), &[]),
+            (Some("Firefox"), &["Firefox"]),
+            (Some("code;Code; code-oss ;"), &["code", "Code", "code-oss"]),
+            (Some(";;"), &[]),
+        ];
+        for (startup_wm_class, expected) in cases {
+            let entry = entry("foo.desktop", startup_wm_class, None);
+            let wm_classes: Vec<&str> = entry.wm_classes().collect();
+            assert_eq!(wm_classes, expected, "StartupWMClass={startup_wm_class:?}");
+        }
+    }
+
+    #[test]
+    fn name_is_localized_for_the_given_locale() {
+        let contents = "[Desktop Entry]\n\
+                        Name=Files\n\
+                        Name[de]=Dateien\n\
+                        Name[de_DE]=Dateien (Deutschland)\n\
+                        [Desktop Action new-window]\n\
+                        Name[de_AT]=Neues Fenster\n";
+        let cases = [
+            ("de_DE.UTF-8", "Dateien (Deutschland)"),
+            ("de_DE@euro", "Dateien (Deutschland)"),
+            ("de_AT.UTF-8", "Dateien"),
+            ("de", "Dateien"),
+            ("sv_SE.UTF-8", "Files"),
+            ("C", "Files"),
+            ("", "Files"),
+        ];
+        for (locale, expected) in cases {
+            let locale_keys = DesktopMatcher::locale_keys(locale);
+            let entry = DesktopMatcher::parse_desktop_entry(
+                Path::new("files.desktop"),
+                contents.as_bytes(),
+                &locale_keys,
+            );
+            assert_eq!(entry.name.as_deref(), Some(expected), "locale={locale}");
+        }
+    }
+
+    #[test]
+    fn locale_keys_follow_the_spec_order() {
+        assert_eq!(
+            DesktopMatcher::locale_keys("sv_SE.UTF-8@euro"),
+            ["sv_SE@euro", "sv_SE", "sv@euro", "sv"]
+        );
+        assert_eq!(DesktopMatcher::locale_keys("de_DE"), ["de_DE", "de"]);
+        assert_eq!(DesktopMatcher::locale_keys("POSIX"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn earlier_data_dirs_take_precedence() {
+        let (user, system) = (TempDir::new(), TempDir::new());
+        user.write(
+            "applications/foo.desktop",
+            "[Desktop Entry]\nName=User Foo\nStartupWMClass=foo-user\n",
+        );
+        system.write(
+            "applications/foo.desktop",
+            "[Desktop Entry]\nName=System Foo\nStartupWMClass=foo-system\n",
+        );
+        let user_editor = user.write(
+            "applications/editor.desktop",
+            "[Desktop Entry]\nName=User Editor\nStartupWMClass=EditorWindow\n",
+        );
+        system.write(
+            "applications/org.example.Editor.desktop",
+            "[Desktop Entry]\nName=System Editor\nStartupWMClass=EditorWindow\n",
+        );
+        let data_dirs = [&user, &system].map(|dir| dir.path().to_string_lossy().into_owned());
+        let mut matcher = DesktopMatcher::empty();
+        matcher.scan_directories(&data_dirs);
+
+        let name = |app_id| matcher.find_desktop_file(app_id)?.name.clone();
+        assert_eq!(name("foo").as_deref(), Some("User Foo"));
+        assert_eq!(name("foo-user").as_deref(), Some("User Foo"));
+        // The shadowed system file's StartupWMClass isn't indexed at all
+        assert_eq!(name("foo-system"), None);
+        assert_eq!(
+            matcher
+                .find_desktop_file("EditorWindow")
+                .map(|entry| &entry.path),
+            Some(&user_editor)
+        );
+        assert_eq!(name("org.example.editor").as_deref(), Some("System Editor"));
+    }
+
+    #[test]
+    fn misses_are_forgotten_when_an_entry_is_indexed() {
+        let mut matcher = DesktopMatcher::empty();
+        assert_eq!(matched_file(&matcher, "gedit"), None);
+        matcher.index_entry(entry("gedit.desktop", None, None));
+        assert_eq!(
+            matched_file(&matcher, "gedit"),
+            Some("gedit.desktop".into())
+        );
+    }
+
+    #[test]
+    fn keys_of_a_deleted_file_go_to_the_entries_next_in_line() {
+        let data_dir = TempDir::new();
+        let one = data_dir.write(
+            "applications/one.desktop",
+            "[Desktop Entry]\nName=One\nStartupWMClass=Shared\nExec=one\n",
+        );
+        let two = data_dir.write(
+            "applications/two.desktop",
+            "[Desktop Entry]\nName=Two\nStartupWMClass=Shared\nExec=two\n",
+        );
+        let data_dirs = [data_dir.path().to_string_lossy().into_owned()];
+        let mut matcher = DesktopMatcher::from_data_dirs(&data_dirs);
+
+        let winner = matcher
+            .find_desktop_file("shared")
+            .map(|entry| entry.path.clone());
+        let (deleted, remaining) = if winner.as_ref() == Some(&one) {
+            (one, two)
+        } else {
+            (two, one)
+        };
+        fs::remove_file(&deleted).unwrap();
+        matcher.reindex_paths_in(&[deleted.clone()], &data_dirs);
+
+        assert_eq!(
+            matcher.find_desktop_file("shared").map(|entry| &entry.path),
+            Some(&remaining)
+        );
+        let program = deleted.file_stem().unwrap().to_str().unwrap();
+        assert_eq!(matched_file(&matcher, program), None);
+    }
+}
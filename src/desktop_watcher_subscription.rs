@@ -0,0 +1,76 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use std::path::{Path, PathBuf};
+
+use cosmic::iced;
+use futures_channel::mpsc;
+use futures_util::StreamExt;
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::desktop_matcher::DesktopMatcher;
+
+/// Creates an iced Subscription that emits the paths of desktop files that were created,
+/// modified or deleted in any of the XDG `applications` directories.
+///
+/// Directories that don't exist when the subscription starts are not watched.
+pub fn desktop_watcher_subscription() -> iced::Subscription<Vec<PathBuf>> {
+    iced::Subscription::run_with_id(
+        "desktop-watcher-sub",
+        futures_util::stream::once(async {
+            let (sender, receiver) = mpsc::unbounded();
+            let watcher =
+                notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+                    Ok(event) => {
+                        if let Some(paths) = changed_desktop_files(event) {
+                            let _ = sender.unbounded_send(paths);
+                        }
+                    }
+                    Err(err) => log::warn!("desktop file watcher error: {err}"),
+                });
+            let watcher = match watcher {
+                Ok(mut watcher) => {
+                    for data_dir in DesktopMatcher::get_xdg_data_dirs() {
+                        let apps_dir = Path::new(&data_dir).join("applications");
+                        if !apps_dir.exists() {
+                            continue;
+                        }
+                        if let Err(err) = watcher.watch(&apps_dir, RecursiveMode::NonRecursive) {
+                            log::warn!("failed to watch {}: {err}", apps_dir.display());
+                        }
+                    }
+                    Some(watcher)
+                }
+                Err(err) => {
+                    log::warn!("failed to create desktop file watcher: {err}");
+                    None
+                }
+            };
+            // The watcher stops when dropped, so it lives alongside the receiver
+            futures_util::stream::unfold(
+                (watcher, receiver),
+                |(watcher, mut receiver)| async move {
+                    watcher.as_ref()?;
+                    let paths = receiver.next().await?;
+                    Some((paths, (watcher, receiver)))
+                },
+            )
+        })
+        .flatten(),
+    )
+}
+
+/// Extracts the desktop file paths touched by a create, modify or remove event.
+fn changed_desktop_files(event: notify::Event) -> Option<Vec<PathBuf>> {
+    if !matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+    ) {
+        return None;
+    }
+    let paths: Vec<PathBuf> = event
+        .paths
+        .into_iter()
+        .filter(|path| path.extension().and_then(|s| s.to_str()) == Some("desktop"))
+        .collect();
+    (!paths.is_empty()).then_some(paths)
+}
@@ -0,0 +1,186 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! On-disk cache of resolved icon paths, so icons show up right away on the next start.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+const CACHE_FILE: &str = "cosmic-applet-workspace-apps/icon-paths.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    /// Describes the lookup settings the path was resolved with, entries resolved with other
+    /// settings are ignored
+    lookup_key: String,
+    path: PathBuf,
+}
+
+/// Changes are kept in memory until [`IconPathCache::flush`], or until the cache is dropped, so
+/// a burst of resolved icons writes the file once.
+#[derive(Debug, Default)]
+pub struct IconPathCache {
+    file: Option<PathBuf>,
+    entries: HashMap<String, Entry>,
+    /// The entries changed since the file was last written
+    dirty: bool,
+}
+
+impl IconPathCache {
+    /// Reads the cache file, dropping entries whose icon file no longer exists.
+    pub fn load() -> Self {
+        let Some(file) = Self::cache_file() else {
+            log::debug!("icon path cache disabled - no XDG_CACHE_HOME or HOME");
+            return Self::default();
+        };
+        Self::load_file(file)
+    }
+
+    /// Reads the cache from `file` rather than the user's cache directory.
+    pub(crate) fn load_file(file: PathBuf) -> Self {
+        let entries = match std::fs::read(&file) {
+            Ok(bytes) => {
+                serde_json::from_slice::<HashMap<String, Entry>>(&bytes).unwrap_or_else(|err| {
+                    log::warn!("icon path cache {} ignored - {err}", file.display());
+                    HashMap::new()
+                })
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => {
+                log::warn!("failed to read icon path cache {}: {err}", file.display());
+                HashMap::new()
+            }
+        };
+        let entries = entries
+            .into_iter()
+            .filter(|(_, entry)| entry.path.exists())
+            .collect();
+        Self {
+            file: Some(file),
+            entries,
+            dirty: false,
+        }
+    }
+
+    pub fn get(&self, app_id: &str, lookup_key: &str) -> Option<PathBuf> {
+        self.entries
+            .get(app_id)
+            .filter(|entry| entry.lookup_key == lookup_key && entry.path.exists())
+            .map(|entry| entry.path.clone())
+    }
+
+    /// Records the resolved path of `app_id`, `None` forgets it.
+    pub fn insert(&mut self, app_id: &str, lookup_key: &str, path: Option<PathBuf>) {
+        let changed = match path {
+            Some(path) => {
+                let entry = Entry {
+                    lookup_key: lookup_key.to_string(),
+                    path,
+                };
+                self.entries.insert(app_id.to_string(), entry.clone()) != Some(entry)
+            }
+            None => self.entries.remove(app_id).is_some(),
+        };
+        self.dirty |= changed;
+    }
+
+    pub fn remove(&mut self, app_id: &str) {
+        self.dirty |= self.entries.remove(app_id).is_some();
+    }
+
+    pub fn clear(&mut self) {
+        self.dirty |= !self.entries.is_empty();
+        self.entries.clear();
+    }
+
+    /// Writes the cache file if the entries changed since it was last written.
+    pub fn flush(&mut self) {
+        if !std::mem::take(&mut self.dirty) {
+            return;
+        }
+        let Some(file) = &self.file else {
+            return;
+        };
+        let result = serde_json::to_vec(&self.entries)
+            .map_err(std::io::Error::from)
+            .and_then(|bytes| {
+                if let Some(dir) = file.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                // Write to a temporary file first so a crash never leaves a truncated cache
+                let tmp = file.with_extension("json.tmp");
+                std::fs::write(&tmp, bytes)?;
+                std::fs::rename(&tmp, file)
+            });
+        if let Err(err) = result {
+            log::warn!("failed to write icon path cache {}: {err}", file.display());
+        }
+    }
+
+    fn cache_file() -> Option<PathBuf> {
+        std::env::var("XDG_CACHE_HOME")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var("HOME")
+                    .ok()
+                    .map(|home| PathBuf::from(home).join(".cache"))
+            })
+            .map(|dir| dir.join(CACHE_FILE))
+    }
+}
+
+impl Drop for IconPathCache {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn changes_are_written_on_flush() {
+        let dir = TempDir::new();
+        let file = dir.path().join("icon-paths.json");
+        let icon = dir.write("icons/foo.svg", "");
+        let mut cache = IconPathCache::load_file(file.clone());
+
+        cache.insert("foo", "key", Some(icon.clone()));
+        cache.insert("bar", "key", None);
+        assert!(!file.exists());
+        cache.flush();
+        assert!(file.exists());
+
+        let mut loaded = IconPathCache::load_file(file.clone());
+        assert_eq!(loaded.get("foo", "key"), Some(icon.clone()));
+        assert_eq!(loaded.get("foo", "other-key"), None);
+
+        // Unchanged entries don't write the file again
+        std::fs::remove_file(&file).expect("remove cache file");
+        loaded.insert("foo", "key", Some(icon));
+        loaded.flush();
+        assert!(!file.exists());
+
+        loaded.remove("foo");
+        drop(loaded);
+        assert_eq!(IconPathCache::load_file(file).get("foo", "key"), None);
+    }
+
+    #[test]
+    fn entries_of_missing_icons_are_dropped_on_load() {
+        let dir = TempDir::new();
+        let file = dir.path().join("icon-paths.json");
+        let icon = dir.write("icons/foo.svg", "");
+        let mut cache = IconPathCache::load_file(file.clone());
+        cache.insert("foo", "key", Some(icon.clone()));
+        cache.flush();
+
+        std::fs::remove_file(&icon).expect("remove icon");
+        assert!(IconPathCache::load_file(file).entries.is_empty());
+    }
+}
@@ -1,58 +1,821 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+    future::Future,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+};
 
 use cosmic::widget;
+use lru::LruCache;
 
-use crate::desktop_matcher::DesktopMatcher;
+use crate::desktop_matcher::{DesktopEntry, DesktopMatcher};
+use crate::icon_path_cache::IconPathCache;
 
 const FALLBACK_ICON: &[u8] = include_bytes!("../resources/fallback-icon.svg");
+const SYMBOLIC_SUFFIX: &str = "-symbolic";
+/// Capacity of the app id cache until [`Icons::set_cache_capacity`] is called, also the
+/// default of the `icon_cache_capacity` setting.
+pub const DEFAULT_CACHE_CAPACITY: NonZeroUsize = NonZeroUsize::new(256).unwrap();
+
+/// Number of icons resolved in the background at once, further ones wait in the queue so a
+/// session with many windows doesn't occupy every blocking thread at startup.
+const MAX_PENDING_RESOLUTIONS: usize = 4;
+
+/// Icon theme used when an icon is missing from the configured themes.
+const HICOLOR_THEME: &str = "hicolor";
+/// File extensions of icons inside icon directories, in order of preference.
+const ICON_EXTENSIONS: [&str; 2] = ["svg", "png"];
+
+/// How icon names are looked up, shared with the background resolution tasks.
+#[derive(Debug, Clone)]
+struct IconLookup {
+    /// Logical size icons are shown at, for picking the closest size of themes without
+    /// scalable icons
+    size: u16,
+    /// Integer scale of the panel's output, so sized icons are picked at physical resolution
+    scale: u16,
+    /// Icon themes in lookup order, before the generic hicolor lookup
+    themes: Vec<String>,
+    /// Try the `-symbolic` variant of a name before the name itself
+    prefer_symbolic: bool,
+    /// Configured icon theme roots searched before the standard locations
+    extra_dirs: Vec<PathBuf>,
+    /// Icon theme roots searched after the standard locations, e.g. Flatpak exports
+    trailing_dirs: Vec<PathBuf>,
+}
+
+impl Default for IconLookup {
+    fn default() -> Self {
+        Self {
+            // The freedesktop lookup defaults
+            size: 24,
+            scale: 1,
+            themes: Vec::new(),
+            prefer_symbolic: false,
+            extra_dirs: Vec::new(),
+            trailing_dirs: Vec::new(),
+        }
+    }
+}
+
+impl IconLookup {
+    /// Identifies these settings in the on-disk icon path cache
+    fn cache_key(&self) -> String {
+        let extra_dirs: Vec<_> = self
+            .extra_dirs
+            .iter()
+            .map(|dir| dir.to_string_lossy())
+            .collect();
+        format!(
+            "{}|{}|{}|{}@{}",
+            self.themes.join(","),
+            self.prefer_symbolic,
+            extra_dirs.join(","),
+            self.size,
+            self.scale
+        )
+    }
+}
+
+/// Outcome of a background resolution, see [`Icons::resolve_icon_if_missing`]
+#[derive(Debug, Clone)]
+pub struct ResolvedIcon {
+    generation: u64,
+    path: Option<PathBuf>,
+}
 
 pub struct Icons {
     fallback_icon: widget::icon::Icon,
-    app_id_cache: HashMap<String, widget::icon::Icon>,
-    desktop_matcher: DesktopMatcher,
+    /// Configured fallback icon name or path, see [`Icons::set_fallback_icon`]
+    fallback_value: Option<String>,
+    /// Resolved icons by app id. Lookups from the view bump recency, hence the RefCell.
+    app_id_cache: RefCell<LruCache<String, widget::icon::Icon>>,
+    /// Cached app ids whose icon couldn't be found, they show the fallback icon
+    unresolved: HashSet<String>,
+    /// App ids whose icon is currently being resolved in the background, with the
+    /// generation the resolution was started in
+    pending: HashMap<String, u64>,
+    /// Bumped by [`Icons::invalidate_all`], results of resolutions started before are dropped
+    generation: u64,
+    /// App ids waiting to be re-resolved a few at a time, see [`Icons::invalidate_all`]
+    queued: VecDeque<String>,
+    /// The app ids in `queued`, so queueing checks for duplicates without a scan
+    queued_ids: HashSet<String>,
+    /// Icons configured by name or path rather than app id, e.g. per-workspace icons
+    named_cache: HashMap<String, widget::icon::Icon>,
+    desktop_matcher: Arc<DesktopMatcher>,
+    /// The desktop's icon theme, see [`Icons::set_icon_theme`]
+    icon_theme: Option<String>,
+    /// Configured icon themes tried after the desktop's one
+    theme_fallbacks: Vec<String>,
+    lookup: Arc<IconLookup>,
+    /// Icon names or paths configured per app id, tried before the desktop entry's icon
+    overrides: HashMap<String, String>,
+    /// Resolved paths from previous runs
+    path_cache: IconPathCache,
+    /// Draw symbolic icons in the panel's foreground color, see [`Icons::set_recolor`]
+    recolor: bool,
 }
 
 impl Icons {
     pub fn new() -> Self {
+        Self::with_parts(DesktopMatcher::new(), IconPathCache::load())
+    }
+
+    /// Icons matching app ids through `desktop_matcher`, remembering resolved paths in
+    /// `path_cache`
+    fn with_parts(desktop_matcher: DesktopMatcher, path_cache: IconPathCache) -> Self {
         Self {
             fallback_icon: widget::icon::from_svg_bytes(FALLBACK_ICON).icon(),
-            app_id_cache: HashMap::new(),
-            desktop_matcher: DesktopMatcher::new(),
+            fallback_value: None,
+            app_id_cache: RefCell::new(LruCache::new(DEFAULT_CACHE_CAPACITY)),
+            unresolved: HashSet::new(),
+            pending: HashMap::new(),
+            generation: 0,
+            queued: VecDeque::new(),
+            queued_ids: HashSet::new(),
+            named_cache: HashMap::new(),
+            desktop_matcher: Arc::new(desktop_matcher),
+            icon_theme: None,
+            theme_fallbacks: Vec::new(),
+            lookup: Arc::new(IconLookup {
+                trailing_dirs: DesktopMatcher::flatpak_export_dirs()
+                    .into_iter()
+                    .map(|dir| Path::new(&dir).join("icons"))
+                    .collect(),
+                ..IconLookup::default()
+            }),
+            overrides: HashMap::new(),
+            path_cache,
+            recolor: false,
         }
     }
 
     pub fn get_icon(&self, app_id: &str) -> widget::icon::Icon {
-        self.app_id_cache.get(app_id).unwrap_or_else(|| &self.fallback_icon).clone()
+        self.app_id_cache
+            .borrow_mut()
+            .get(app_id)
+            .unwrap_or_else(|| &self.fallback_icon)
+            .clone()
+    }
+
+    /// Whether `app_id` shows the fallback icon, because its icon wasn't found or isn't
+    /// resolved yet
+    pub fn is_fallback(&self, app_id: &str) -> bool {
+        !self.app_id_cache.borrow().contains(app_id) || self.unresolved.contains(app_id)
+    }
+
+    /// Bounds the number of cached app icons, evicting the least recently used ones. Evicted
+    /// icons of shown windows are resolved again on the next window update.
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(DEFAULT_CACHE_CAPACITY);
+        self.app_id_cache.get_mut().resize(capacity);
+    }
+
+    /// Sets the themes searched after the desktop's one. Returns whether they changed, in which
+    /// case cached icons should be invalidated.
+    pub fn set_theme_fallbacks(&mut self, themes: &[String]) -> bool {
+        if self.theme_fallbacks == themes {
+            return false;
+        }
+        self.theme_fallbacks = themes.to_vec();
+        self.update_lookup();
+        true
+    }
+
+    /// Sets the desktop's icon theme, searched first. Returns whether it changed, in which case
+    /// cached icons should be invalidated.
+    pub fn set_icon_theme(&mut self, theme: &str) -> bool {
+        let theme = (!theme.is_empty()).then(|| theme.to_string());
+        if self.icon_theme == theme {
+            return false;
+        }
+        log::info!("icon theme changed to {theme:?}");
+        self.icon_theme = theme;
+        self.update_lookup();
+        true
+    }
+
+    /// Sets the icon shown for apps whose icon can't be resolved, an icon name or absolute
+    /// path. The embedded icon is used if unset or not found. The icon is looked up again
+    /// on every call so lookup setting changes apply. Returns whether the value changed, in
+    /// which case cached icons should be invalidated.
+    pub fn set_fallback_icon(&mut self, value: Option<&str>) -> bool {
+        let changed = self.fallback_value.as_deref() != value;
+        self.fallback_value = value.map(String::from);
+        let path = value.and_then(|value| {
+            let path = Self::lookup_icon_value(value, &self.lookup);
+            if path.is_none() {
+                log::warn!("fallback_icon={value} ignored - no icon with that name or path found");
+            }
+            path
+        });
+        self.fallback_icon = match path {
+            Some(path) => Self::icon_from_path(path, self.recolor),
+            None => widget::icon::from_svg_bytes(FALLBACK_ICON).icon(),
+        };
+        changed
+    }
+
+    /// Makes lookups try the `-symbolic` variant of icon names first. Returns whether this
+    /// changed, in which case cached icons should be invalidated.
+    pub fn set_prefer_symbolic(&mut self, prefer_symbolic: bool) -> bool {
+        if self.lookup.prefer_symbolic == prefer_symbolic {
+            return false;
+        }
+        self.lookup = Arc::new(IconLookup {
+            prefer_symbolic,
+            ..(*self.lookup).clone()
+        });
+        true
+    }
+
+    /// Draws symbolic SVG icons in the panel's foreground color rather than the color of the
+    /// surrounding text. Color icons are left as they are, unless symbolic variants are
+    /// preferred. Returns whether this changed, in which case cached icons should be invalidated.
+    pub fn set_recolor(&mut self, recolor: bool) -> bool {
+        if self.recolor == recolor {
+            return false;
+        }
+        self.recolor = recolor;
+        true
+    }
+
+    /// Sets the logical size icons are shown at and the scale of the output they are shown
+    /// on. Returns whether either changed, in which case cached icons should be invalidated.
+    pub fn set_lookup_size(&mut self, size: u16, scale: u16) -> bool {
+        if self.lookup.size == size && self.lookup.scale == scale {
+            return false;
+        }
+        self.lookup = Arc::new(IconLookup {
+            size,
+            scale,
+            ..(*self.lookup).clone()
+        });
+        true
+    }
+
+    /// Sets icon theme roots searched before the standard locations, for icons installed to
+    /// unusual places. Returns whether they changed, in which case cached icons should be
+    /// invalidated.
+    pub fn set_extra_dirs(&mut self, dirs: &[PathBuf]) -> bool {
+        if self.lookup.extra_dirs == dirs {
+            return false;
+        }
+        self.lookup = Arc::new(IconLookup {
+            extra_dirs: dirs.to_vec(),
+            ..(*self.lookup).clone()
+        });
+        true
+    }
+
+    fn update_lookup(&mut self) {
+        let themes = self
+            .icon_theme
+            .iter()
+            .chain(&self.theme_fallbacks)
+            .cloned()
+            .collect();
+        self.lookup = Arc::new(IconLookup {
+            themes,
+            ..(*self.lookup).clone()
+        });
+    }
+
+    /// Sets the per app id icon overrides. Returns whether they changed, in which case cached
+    /// icons should be invalidated.
+    pub fn set_overrides(&mut self, overrides: &HashMap<String, String>) -> bool {
+        if self.overrides == *overrides {
+            return false;
+        }
+        self.overrides = overrides.clone();
+        true
+    }
+
+    /// Human-friendly application name from the desktop entry matching `app_id`
+    pub fn app_name(&self, app_id: &str) -> Option<String> {
+        self.desktop_entry(app_id)
+            .and_then(|entry| entry.name.clone())
+    }
+
+    pub fn desktop_entry(&self, app_id: &str) -> Option<&DesktopEntry> {
+        self.desktop_matcher.find_desktop_file(app_id)
+    }
+
+    /// Returns a future resolving the icon path of `app_id`, unless the icon is already cached
+    /// or being resolved. The future does its filesystem work on a blocking thread, so the UI
+    /// keeps rendering the fallback icon until the result is passed to [`Icons::insert_resolved`].
+    /// Beyond [`MAX_PENDING_RESOLUTIONS`] the app id is queued for [`Icons::take_queued`].
+    pub fn resolve_icon_if_missing(
+        &mut self,
+        app_id: &str,
+    ) -> Option<impl Future<Output = ResolvedIcon> + Send + 'static> {
+        if self.app_id_cache.get_mut().contains(app_id) || self.queued_ids.contains(app_id) {
+            return None;
+        }
+        if !self.overrides.contains_key(app_id) {
+            if let Some(path) = self.path_cache.get(app_id, &self.lookup.cache_key()) {
+                let icon = Self::icon_from_path(path, self.recolor);
+                self.unresolved.remove(app_id);
+                self.app_id_cache.get_mut().put(app_id.to_string(), icon);
+                return None;
+            }
+        }
+        if self.pending.get(app_id) == Some(&self.generation) {
+            return None;
+        }
+        if self.pending.len() >= MAX_PENDING_RESOLUTIONS {
+            self.enqueue(app_id.to_string());
+            return None;
+        }
+        self.pending.insert(app_id.to_string(), self.generation);
+        let generation = self.generation;
+        let desktop_matcher = self.desktop_matcher.clone();
+        let lookup = self.lookup.clone();
+        let override_value = self.overrides.get(app_id).cloned();
+        let app_id = app_id.to_string();
+        Some(async move {
+            let path = tokio::task::spawn_blocking(move || {
+                let started = std::time::Instant::now();
+                let overridden = override_value.and_then(|value| {
+                    let path = Self::lookup_icon_value(&value, &lookup);
+                    if path.is_none() {
+                        log::warn!("app_id={app_id} icon override {value} not found");
+                    }
+                    path
+                });
+                let path = overridden
+                    .or_else(|| Self::resolve_icon_path(&desktop_matcher, &lookup, &app_id));
+                log::debug!(
+                    "app_id={app_id} icon resolved to {path:?} in {:?}",
+                    started.elapsed()
+                );
+                path
+            })
+            .await
+            .ok()
+            .flatten();
+            ResolvedIcon { generation, path }
+        })
+    }
+
+    /// Stores the outcome of a background resolution started by [`Icons::resolve_icon_if_missing`].
+    /// Results started before the last [`Icons::invalidate_all`] are dropped, they were looked
+    /// up with outdated settings.
+    pub fn insert_resolved(&mut self, app_id: String, resolved: ResolvedIcon) {
+        if self.pending.get(&app_id) == Some(&resolved.generation) {
+            self.pending.remove(&app_id);
+        }
+        if resolved.generation != self.generation {
+            log::debug!("app_id={app_id} icon resolution dropped - started before invalidation");
+            return;
+        }
+        let icon_path = resolved.path;
+        if !self.overrides.contains_key(&app_id) {
+            self.path_cache
+                .insert(&app_id, &self.lookup.cache_key(), icon_path.clone());
+        }
+        let icon = if let Some(path) = icon_path {
+            self.unresolved.remove(&app_id);
+            Self::icon_from_path(path, self.recolor)
+        } else {
+            self.unresolved.insert(app_id.clone());
+            self.fallback_icon.clone()
+        };
+        self.app_id_cache.get_mut().put(app_id, icon);
+        // Written once the burst of resolutions, e.g. at startup, is over
+        if self.pending.is_empty() && self.queued.is_empty() {
+            self.path_cache.flush();
+        }
+    }
+
+    /// Drops every cached icon and queues `app_ids` for re-resolution. Queued ids show the
+    /// fallback icon until [`Icons::take_queued`] hands them out for resolving.
+    pub fn invalidate_all(&mut self, app_ids: impl IntoIterator<Item = String>) {
+        self.generation += 1;
+        self.app_id_cache.get_mut().clear();
+        self.unresolved.clear();
+        self.queued.clear();
+        self.queued_ids.clear();
+        for app_id in app_ids {
+            self.enqueue(app_id);
+        }
+    }
+
+    /// Queues `app_id` for [`Icons::take_queued`] unless it is queued already.
+    fn enqueue(&mut self, app_id: String) {
+        if self.queued_ids.insert(app_id.clone()) {
+            self.queued.push_back(app_id);
+        }
+    }
+
+    /// Forgets the icon paths resolved in previous runs.
+    pub fn clear_path_cache(&mut self) {
+        self.path_cache.clear();
+        self.path_cache.flush();
     }
 
-    pub fn load_icon_if_missing(&mut self, app_id: &str) {
-        if !self.app_id_cache.contains_key(app_id) {
-            let icon = self.load_icon(app_id);
-            self.app_id_cache.insert(app_id.to_string(), icon);
+    pub fn has_queued(&self) -> bool {
+        !self.queued.is_empty()
+    }
+
+    /// Removes up to `count` app ids from the front of the queue, fewer if that many can't be
+    /// resolved at once.
+    pub fn take_queued(&mut self, count: usize) -> Vec<String> {
+        let free = MAX_PENDING_RESOLUTIONS.saturating_sub(self.pending.len());
+        let count = count.min(free).min(self.queued.len());
+        let taken: Vec<String> = self.queued.drain(..count).collect();
+        for app_id in &taken {
+            self.queued_ids.remove(app_id);
+        }
+        taken
+    }
+
+    /// Updates the desktop index for changed desktop files and drops cached icons of app ids
+    /// whose match was, or now is, one of those files.
+    pub fn desktop_files_changed(&mut self, paths: &[PathBuf]) {
+        let matches_changed = |matcher: &DesktopMatcher, app_id: &str| {
+            matcher
+                .find_desktop_file(app_id)
+                // By name, a changed file may shadow or uncover one in another directory
+                .is_some_and(|entry| {
+                    paths
+                        .iter()
+                        .any(|path| path.file_name() == entry.path.file_name())
+                })
+        };
+        let cache = self.app_id_cache.get_mut();
+        let mut stale: HashSet<String> = cache
+            .iter()
+            .map(|(app_id, _)| app_id)
+            .filter(|app_id| matches_changed(&self.desktop_matcher, app_id))
+            .cloned()
+            .collect();
+
+        Arc::make_mut(&mut self.desktop_matcher).reindex_paths(paths);
+
+        stale.extend(
+            cache
+                .iter()
+                .map(|(app_id, _)| app_id)
+                .filter(|app_id| matches_changed(&self.desktop_matcher, app_id))
+                .cloned(),
+        );
+        for app_id in &stale {
+            log::debug!("app_id={app_id} icon invalidated - desktop file changed");
+            cache.pop(app_id);
+            self.path_cache.remove(app_id);
+        }
+        self.path_cache.flush();
+    }
+
+    /// Swaps in a freshly scanned desktop index, e.g. one built by a background task.
+    pub fn set_desktop_matcher(&mut self, desktop_matcher: Arc<DesktopMatcher>) {
+        self.desktop_matcher = desktop_matcher;
+    }
+
+    /// Replaces the configured named icons. Each value is an icon name or an absolute path;
+    /// values that can't be resolved are logged and skipped.
+    pub fn set_named_icons<'a>(&mut self, values: impl IntoIterator<Item = &'a String>) {
+        self.named_cache.clear();
+        for value in values {
+            if let Some(path) = Self::lookup_icon_value(value, &self.lookup) {
+                self.named_cache
+                    .insert(value.clone(), Self::icon_from_path(path, self.recolor));
+            } else {
+                log::warn!("icon={value} ignored - no icon with that name or path found");
+            }
+        }
+    }
+
+    pub fn get_named_icon(&self, value: &str) -> Option<widget::icon::Icon> {
+        self.named_cache.get(value).cloned()
+    }
+
+    /// Loads an icon file, symbolic ones get recolored to match the surrounding text, or to the
+    /// panel's foreground color with `recolor`. Raster icons are drawn as they are.
+    fn icon_from_path(path: PathBuf, recolor: bool) -> widget::icon::Icon {
+        let is_symbolic = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .is_some_and(|stem| stem.ends_with(SYMBOLIC_SUFFIX));
+        let is_svg = path.extension().is_some_and(|extension| extension == "svg");
+        let mut handle = widget::icon::from_path(path);
+        handle.symbolic = is_symbolic;
+        let icon = handle.icon();
+        if recolor && is_symbolic && is_svg {
+            icon.class(cosmic::theme::Svg::Custom(Rc::new(
+                |theme: &cosmic::Theme| cosmic::iced::widget::svg::Style {
+                    color: Some(theme.cosmic().on_bg_color().into()),
+                },
+            )))
+        } else {
+            icon
         }
     }
 
-    fn load_icon(&self, app_id: &str) -> widget::icon::Icon {
-        let icon_value = self
-            .desktop_matcher
+    fn resolve_icon_path(
+        desktop_matcher: &DesktopMatcher,
+        lookup: &IconLookup,
+        app_id: &str,
+    ) -> Option<PathBuf> {
+        let icon_value = desktop_matcher
             .find_desktop_file(app_id)
             .map(|df| df.icon.clone())
             .flatten();
-        let icon_path = match icon_value {
-            Some(ref icon_value) if PathBuf::from(icon_value).is_absolute() => {
-                Some(PathBuf::from(icon_value))
+        match icon_value {
+            Some(ref icon_value) => Self::lookup_icon_value(icon_value, lookup),
+            None => Self::lookup_icon_path(app_id, lookup),
+        }
+    }
+
+    /// Resolves an icon value that is either an absolute path or an icon name
+    fn lookup_icon_value(icon_value: &str, lookup: &IconLookup) -> Option<PathBuf> {
+        let path = PathBuf::from(icon_value);
+        if path.is_absolute() {
+            path.exists().then_some(path)
+        } else {
+            Self::lookup_icon_path(icon_value, lookup)
+        }
+    }
+
+    /// Looks `name` up, preceded by its symbolic variant if preferred
+    fn lookup_icon_path(name: &str, lookup: &IconLookup) -> Option<PathBuf> {
+        let symbolic = (lookup.prefer_symbolic && !name.ends_with(SYMBOLIC_SUFFIX))
+            .then(|| format!("{name}{SYMBOLIC_SUFFIX}"));
+        symbolic
+            .and_then(|symbolic| Self::lookup_in_themes(&symbolic, lookup))
+            .or_else(|| Self::lookup_in_themes(name, lookup))
+    }
+
+    /// Looks `name` up in each of the lookup's themes in order, then in the generic hicolor
+    /// theme, with the extra directories searched before and after the standard locations
+    fn lookup_in_themes(name: &str, lookup: &IconLookup) -> Option<PathBuf> {
+        let in_dirs = |dirs: &[PathBuf]| {
+            dirs.iter()
+                .find_map(|dir| Self::lookup_in_dir(dir, name, &lookup.themes))
+        };
+        let sized = || {
+            freedesktop_icons::lookup(name)
+                .with_size(lookup.size)
+                .with_scale(lookup.scale)
+        };
+        in_dirs(&lookup.extra_dirs)
+            .or_else(|| {
+                lookup
+                    .themes
+                    .iter()
+                    .find_map(|theme| sized().with_theme(theme).find())
+            })
+            .or_else(|| sized().find())
+            .or_else(|| in_dirs(&lookup.trailing_dirs))
+    }
+
+    /// Looks `name` up in an icon theme root, in each of `themes` and hicolor, preferring
+    /// scalable icons over the largest sized ones. Icons placed directly in `root` are tried
+    /// last.
+    fn lookup_in_dir(root: &Path, name: &str, themes: &[String]) -> Option<PathBuf> {
+        let sub_dirs = |dir: &Path| -> Vec<PathBuf> {
+            fs::read_dir(dir)
+                .map(|entries| {
+                    entries
+                        .flatten()
+                        .map(|entry| entry.path())
+                        .filter(|path| path.is_dir())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let find_file = |dir: &Path| {
+            ICON_EXTENSIONS
+                .iter()
+                .map(|extension| dir.join(format!("{name}.{extension}")))
+                .find(|path| path.is_file())
+        };
+        // Sizes rank by their leading number, e.g. 48 for `48x48@2`, scalable above all
+        let size_rank = |size_dir: &Path| {
+            let size = size_dir
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default();
+            if size == "scalable" {
+                return u32::MAX;
             }
-            Some(ref icon_value) => Self::lookup_icon_path(&icon_value),
-            None => Self::lookup_icon_path(app_id),
+            let digits = size
+                .split(|c: char| !c.is_ascii_digit())
+                .next()
+                .unwrap_or_default();
+            digits.parse().unwrap_or(0)
         };
-        if let Some(path) = icon_path {
-            widget::icon::from_path(path).icon()
-        } else {
-            self.fallback_icon.clone()
+        let themed = themes
+            .iter()
+            .map(String::as_str)
+            .chain([HICOLOR_THEME])
+            .find_map(|theme| {
+                // Theme directories are laid out as either size/context or context/size
+                sub_dirs(&root.join(theme))
+                    .into_iter()
+                    .flat_map(|outer| {
+                        let inner = sub_dirs(&outer);
+                        inner.into_iter().map(move |inner| (outer.clone(), inner))
+                    })
+                    .filter_map(|(outer, inner)| {
+                        let rank = size_rank(&outer).max(size_rank(&inner));
+                        find_file(&inner).map(|path| (rank, path))
+                    })
+                    .max_by_key(|(rank, _)| *rank)
+                    .map(|(_, path)| path)
+            });
+        themed.or_else(|| find_file(root))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    /// Icons indexing only the desktop files in `data_dir`, with their path cache in there too
+    fn icons(data_dir: &TempDir) -> Icons {
+        let data_dirs = [data_dir.path().to_string_lossy().into_owned()];
+        Icons::with_parts(
+            DesktopMatcher::from_data_dirs(&data_dirs),
+            IconPathCache::load_file(data_dir.path().join("icon-paths.json")),
+        )
+    }
+
+    fn id(index: usize) -> String {
+        format!("test.queue.app-{index}")
+    }
+
+    fn ids(range: std::ops::Range<usize>) -> Vec<String> {
+        range.map(id).collect()
+    }
+
+    /// Marks `app_ids` as being resolved in the current generation
+    fn occupy(icons: &mut Icons, app_ids: Vec<String>) {
+        let generation = icons.generation;
+        icons
+            .pending
+            .extend(app_ids.into_iter().map(|app_id| (app_id, generation)));
+    }
+
+    /// Outcome of a resolution started in the current generation that found no icon
+    fn not_found(icons: &Icons) -> ResolvedIcon {
+        ResolvedIcon {
+            generation: icons.generation,
+            path: None,
+        }
+    }
+
+    #[test]
+    fn lookup_in_dir_tries_themes_in_order_then_hicolor() {
+        let root = TempDir::new();
+        let papirus = root.write("Papirus/48x48/apps/foo.svg", "");
+        let adwaita = root.write("Adwaita/apps/64/foo.png", "");
+        let hicolor = root.write("hicolor/scalable/apps/foo.svg", "");
+        let lookup = |themes: &[&str]| {
+            let themes: Vec<String> = themes.iter().map(|theme| theme.to_string()).collect();
+            Icons::lookup_in_dir(root.path(), "foo", &themes)
+        };
+        assert_eq!(lookup(&["Papirus", "Adwaita"]), Some(papirus.clone()));
+        assert_eq!(lookup(&["Missing", "Adwaita", "Papirus"]), Some(adwaita));
+        assert_eq!(lookup(&["Missing"]), Some(hicolor.clone()));
+        assert_eq!(lookup(&[]), Some(hicolor));
+        assert_eq!(Icons::lookup_in_dir(root.path(), "bar", &[]), None);
+
+        let unthemed = root.write("bar.png", "");
+        assert_eq!(lookup(&["Papirus"]), Some(papirus));
+        assert_eq!(
+            Icons::lookup_in_dir(root.path(), "bar", &[]),
+            Some(unthemed)
+        );
+    }
+
+    #[test]
+    fn lookup_in_dir_prefers_scalable_then_larger_icons() {
+        let root = TempDir::new();
+        root.write("hicolor/16x16/apps/foo.png", "");
+        let large = root.write("hicolor/48x48@2/apps/foo.png", "");
+        root.write("hicolor/apps/32/foo.png", "");
+        assert_eq!(Icons::lookup_in_dir(root.path(), "foo", &[]), Some(large));
+
+        let scalable = root.write("hicolor/scalable/apps/foo.svg", "");
+        assert_eq!(
+            Icons::lookup_in_dir(root.path(), "foo", &[]),
+            Some(scalable)
+        );
+    }
+
+    #[test]
+    fn invalidate_all_queues_each_app_id_once_in_order() {
+        let data_dir = TempDir::new();
+        let mut icons = icons(&data_dir);
+        let mut app_ids = ids(0..3);
+        app_ids.push(app_ids[0].clone());
+        icons.invalidate_all(app_ids);
+        assert!(icons.has_queued());
+        assert_eq!(icons.take_queued(usize::MAX), ids(0..3));
+        assert!(!icons.has_queued());
+    }
+
+    #[test]
+    fn take_queued_hands_out_batches_from_the_front() {
+        let data_dir = TempDir::new();
+        let mut icons = icons(&data_dir);
+        icons.invalidate_all(ids(0..5));
+        assert_eq!(icons.take_queued(2), ids(0..2));
+        assert_eq!(icons.take_queued(2), ids(2..4));
+        assert_eq!(icons.take_queued(2), ids(4..5));
+        assert!(icons.take_queued(2).is_empty());
+    }
+
+    #[test]
+    fn take_queued_is_capped_by_free_resolution_slots() {
+        let data_dir = TempDir::new();
+        let mut icons = icons(&data_dir);
+        icons.invalidate_all(ids(0..MAX_PENDING_RESOLUTIONS + 2));
+        occupy(&mut icons, ids(100..MAX_PENDING_RESOLUTIONS + 99));
+        assert_eq!(icons.take_queued(usize::MAX), ids(0..1));
+        occupy(&mut icons, ids(200..201));
+        assert!(icons.take_queued(usize::MAX).is_empty());
+        assert!(icons.has_queued());
+    }
+
+    #[test]
+    fn resolutions_beyond_the_limit_are_queued() {
+        let data_dir = TempDir::new();
+        let mut icons = icons(&data_dir);
+        for app_id in ids(0..MAX_PENDING_RESOLUTIONS) {
+            assert!(icons.resolve_icon_if_missing(&app_id).is_some());
         }
+        let app_id = id(MAX_PENDING_RESOLUTIONS);
+        assert!(icons.resolve_icon_if_missing(&app_id).is_none());
+        assert!(icons.has_queued());
+
+        icons.insert_resolved(id(0), not_found(&icons));
+        assert_eq!(icons.take_queued(usize::MAX), vec![app_id]);
     }
 
-    fn lookup_icon_path(name: &str) -> Option<PathBuf> {
-        freedesktop_icons::lookup(name).find()
+    #[test]
+    fn queued_app_ids_are_not_queued_again() {
+        let data_dir = TempDir::new();
+        let mut icons = icons(&data_dir);
+        for app_id in ids(0..MAX_PENDING_RESOLUTIONS) {
+            assert!(icons.resolve_icon_if_missing(&app_id).is_some());
+        }
+        for _ in 0..3 {
+            assert!(icons.resolve_icon_if_missing(&id(100)).is_none());
+            assert!(icons.resolve_icon_if_missing(&id(101)).is_none());
+        }
+        icons.invalidate_all([id(101), id(102)]);
+        assert!(icons.resolve_icon_if_missing(&id(102)).is_none());
+        icons.pending.clear();
+        assert_eq!(icons.take_queued(usize::MAX), [id(101), id(102)]);
+
+        // Taken ids can be queued again
+        occupy(&mut icons, ids(0..MAX_PENDING_RESOLUTIONS));
+        assert!(icons.resolve_icon_if_missing(&id(101)).is_none());
+        icons.pending.clear();
+        assert_eq!(icons.take_queued(usize::MAX), [id(101)]);
+    }
+
+    #[test]
+    fn invalidate_all_drops_cached_icons() {
+        let data_dir = TempDir::new();
+        let mut icons = icons(&data_dir);
+        let app_id = id(0);
+        icons.insert_resolved(app_id.clone(), not_found(&icons));
+        assert!(icons.is_fallback(&app_id));
+        assert!(icons.resolve_icon_if_missing(&app_id).is_none());
+
+        icons.invalidate_all([app_id.clone()]);
+        assert_eq!(icons.take_queued(1), vec![app_id.clone()]);
+        assert!(icons.resolve_icon_if_missing(&app_id).is_some());
+    }
+
+    #[test]
+    fn resolutions_started_before_invalidation_are_dropped() {
+        let data_dir = TempDir::new();
+        let mut icons = icons(&data_dir);
+        let app_id = id(0);
+        assert!(icons.resolve_icon_if_missing(&app_id).is_some());
+        let stale = not_found(&icons);
+
+        icons.invalidate_all([app_id.clone()]);
+        assert_eq!(icons.take_queued(1), vec![app_id.clone()]);
+        // The running resolution doesn't count, it uses the old settings
+        assert!(icons.resolve_icon_if_missing(&app_id).is_some());
+        icons.insert_resolved(app_id.clone(), stale);
+        assert!(!icons.app_id_cache.get_mut().contains(&app_id));
+        assert!(icons.resolve_icon_if_missing(&app_id).is_none());
+
+        icons.insert_resolved(app_id.clone(), not_found(&icons));
+        assert!(icons.app_id_cache.get_mut().contains(&app_id));
+        assert!(icons.pending.is_empty());
     }
 }
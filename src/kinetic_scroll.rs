@@ -0,0 +1,85 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Inertial scrolling with snapping for the scrollable pager.
+
+/// Fraction of the velocity kept after each tick.
+const FRICTION: f32 = 0.85;
+/// Velocity (pixels per tick) below which the scroll snaps and stops.
+const MIN_VELOCITY: f32 = 0.5;
+/// Pixels scrolled per wheel line.
+const PIXELS_PER_LINE: f32 = 20.0;
+
+#[derive(Debug, Default, Clone)]
+pub struct KineticScroll {
+    offset: f32,
+    velocity: f32,
+    /// Extents along the scroll axis
+    content_width: f32,
+    viewport_width: f32,
+    item_count: usize,
+}
+
+impl KineticScroll {
+    /// Records the scrollable viewport, e.g. after the pager was scrolled or resized.
+    pub fn set_viewport(
+        &mut self,
+        offset: f32,
+        content_width: f32,
+        viewport_width: f32,
+        item_count: usize,
+    ) {
+        if !self.is_animating() {
+            self.offset = offset;
+        }
+        self.content_width = content_width;
+        self.viewport_width = viewport_width;
+        self.item_count = item_count;
+    }
+
+    /// Adds momentum from a scroll gesture.
+    pub fn fling(&mut self, delta: cosmic::iced::mouse::ScrollDelta) {
+        let pixels = match delta {
+            cosmic::iced::mouse::ScrollDelta::Lines { x, y } => {
+                (if x != 0.0 { x } else { y }) * PIXELS_PER_LINE
+            }
+            cosmic::iced::mouse::ScrollDelta::Pixels { x, y } => {
+                if x != 0.0 {
+                    x
+                } else {
+                    y
+                }
+            }
+        };
+        // Scrolling down or left moves the pager towards later workspaces
+        self.velocity -= pixels * (1.0 - FRICTION);
+    }
+
+    pub fn is_animating(&self) -> bool {
+        self.velocity.abs() >= MIN_VELOCITY
+    }
+
+    /// Advances the animation by one tick and returns the new offset. Once the scroll
+    /// slows down enough the offset snaps to the nearest workspace boundary.
+    pub fn tick(&mut self) -> f32 {
+        self.offset = (self.offset + self.velocity).clamp(0.0, self.max_offset());
+        self.velocity *= FRICTION;
+        if !self.is_animating() {
+            self.velocity = 0.0;
+            self.offset = self.snapped(self.offset);
+        }
+        self.offset
+    }
+
+    fn max_offset(&self) -> f32 {
+        (self.content_width - self.viewport_width).max(0.0)
+    }
+
+    /// Rounds `offset` to a workspace boundary, assuming equally wide workspaces.
+    fn snapped(&self, offset: f32) -> f32 {
+        if self.item_count == 0 || self.content_width <= 0.0 {
+            return offset;
+        }
+        let item_width = self.content_width / self.item_count as f32;
+        ((offset / item_width).round() * item_width).clamp(0.0, self.max_offset())
+    }
+}
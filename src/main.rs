@@ -2,12 +2,52 @@
 
 mod app;
 mod config;
+#[cfg(feature = "dbus-service")]
+mod dbus_service;
+mod desktop_matcher;
+mod desktop_watcher_subscription;
 mod i18n;
-mod wayland_subscription;
+mod icon_path_cache;
 mod icons;
-mod desktop_matcher;
+mod kinetic_scroll;
+mod signal_subscription;
+mod snapshot;
+mod statusline;
+#[cfg(test)]
+mod test_util;
+mod wayland_mock;
+mod wayland_subscription;
+mod workspace_model;
+mod workspace_widget;
 
 fn main() -> cosmic::iced::Result {
+    // Verbosity is controlled through RUST_LOG, e.g. RUST_LOG=cosmic_applet_workspaces_apps=debug
+    let env = env_logger::Env::default().default_filter_or("warn");
+    if let Err(err) = env_logger::Builder::from_env(env).try_init() {
+        eprintln!("logging unavailable: {err}");
+    }
+
+    // Print how an app id is matched to a desktop file, for troubleshooting missing icons
+    let mut args = std::env::args().skip_while(|arg| arg != desktop_matcher::EXPLAIN_FLAG);
+    if args.next().is_some() {
+        match args.next() {
+            Some(app_id) => {
+                println!(
+                    "{}",
+                    desktop_matcher::DesktopMatcher::new().explain(&app_id)
+                );
+            }
+            None => eprintln!("usage: {} <app_id>", desktop_matcher::EXPLAIN_FLAG),
+        }
+        return Ok(());
+    }
+
+    // Print an i3bar/swaybar status line instead of running as an applet
+    if std::env::args().any(|arg| arg == statusline::FLAG) {
+        statusline::run(&app::AppModel::load_config().1);
+        return Ok(());
+    }
+
     // Get the system's preferred languages.
     let requested_languages = i18n_embed::DesktopLanguageRequester::requested_languages();
 
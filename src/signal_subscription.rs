@@ -0,0 +1,26 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use cosmic::iced;
+use futures_util::StreamExt;
+use tokio::signal::unix::{SignalKind, signal};
+
+/// Creates an iced Subscription that emits an event every time the process receives SIGUSR1.
+///
+/// The signal handler installed by tokio only records that the signal arrived, which keeps
+/// it async-signal-safe. The actual work happens when the stream below is polled.
+pub fn reload_subscription() -> iced::Subscription<()> {
+    iced::Subscription::run_with_id(
+        "sigusr1-sub",
+        futures_util::stream::once(async {
+            let sigusr1 = signal(SignalKind::user_defined1())
+                .map_err(|err| log::error!("failed to install SIGUSR1 handler: {err}"))
+                .ok();
+            futures_util::stream::unfold(sigusr1, |sigusr1| async move {
+                let mut sigusr1 = sigusr1?;
+                sigusr1.recv().await?;
+                Some(((), Some(sigusr1)))
+            })
+        })
+        .flatten(),
+    )
+}
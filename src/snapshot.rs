@@ -0,0 +1,56 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Serializable summary of the workspace and window state rendered by the applet.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use wayland_protocols::ext::workspace::v1::client::ext_workspace_handle_v1::ExtWorkspaceHandleV1;
+
+use crate::wayland_subscription::{AppToplevel, AppWorkspace};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Snapshot {
+    pub workspaces: Vec<WorkspaceSnapshot>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct WorkspaceSnapshot {
+    pub name: String,
+    pub is_active: bool,
+    pub windows: Vec<WindowSnapshot>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct WindowSnapshot {
+    pub app_id: String,
+    pub is_active: bool,
+    pub is_minimized: bool,
+}
+
+impl Snapshot {
+    /// Builds a snapshot from workspaces in render order and their sorted toplevels.
+    pub fn new(
+        workspaces: &[AppWorkspace],
+        workspace_toplevels: &HashMap<ExtWorkspaceHandleV1, Vec<AppToplevel>>,
+    ) -> Self {
+        let workspaces = workspaces
+            .iter()
+            .map(|workspace| WorkspaceSnapshot {
+                name: workspace.name.clone(),
+                is_active: workspace.is_active,
+                windows: workspace_toplevels
+                    .get(&workspace.handle)
+                    .into_iter()
+                    .flatten()
+                    .map(|toplevel| WindowSnapshot {
+                        app_id: toplevel.app_id.clone(),
+                        is_active: toplevel.is_active,
+                        is_minimized: toplevel.is_minimized,
+                    })
+                    .collect(),
+            })
+            .collect();
+        Self { workspaces }
+    }
+}
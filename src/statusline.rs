@@ -0,0 +1,128 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Writes the workspace/window summary to stdout using the i3bar/swaybar JSON protocol,
+//! so the applet logic can back a text status bar outside of COSMIC.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::Duration;
+
+use cosmic::cctk::wayland_client::Connection;
+use futures_util::StreamExt;
+use serde::Serialize;
+use wayland_protocols::ext::workspace::v1::client::ext_workspace_handle_v1::ExtWorkspaceHandleV1;
+
+use crate::config::Config;
+use crate::snapshot::Snapshot;
+use crate::wayland_subscription::{self, AppToplevel, AppWorkspace, WaylandEvent};
+
+/// Command line flag selecting the status line mode.
+pub const FLAG: &str = "--statusline";
+
+/// One block of an i3bar status line.
+#[derive(Serialize)]
+struct Block {
+    name: &'static str,
+    instance: String,
+    full_text: String,
+    color: &'static str,
+}
+
+/// Runs the status line until stdout is closed or the compositor connection fails. Windows
+/// are filtered and sorted as in the panel.
+pub fn run(config: &Config) {
+    let interval = Duration::from_millis(config.statusline_interval_ms);
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            log::error!("failed to start tokio runtime: {err}");
+            return;
+        }
+    };
+    runtime.block_on(async move {
+        let conn = match Connection::connect_to_env() {
+            Ok(conn) => conn,
+            Err(err) => {
+                log::error!("failed to connect to wayland: {err}");
+                return;
+            }
+        };
+        let mut events = wayland_subscription::start(conn).await;
+
+        let mut stdout = std::io::stdout().lock();
+        if writeln!(stdout, "{{\"version\":1}}\n[").is_err() {
+            return;
+        }
+
+        let mut workspaces: Vec<AppWorkspace> = Vec::new();
+        let mut workspace_toplevels: HashMap<ExtWorkspaceHandleV1, Vec<AppToplevel>> =
+            HashMap::new();
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                event = events.next() => match event {
+                    Some(WaylandEvent::WorkspacesChanged(new_workspaces)) => {
+                        workspaces = new_workspaces;
+                        workspaces.sort_by(AppWorkspace::cmp_coordinates);
+                    }
+                    Some(WaylandEvent::ToplevelsUpdated(ws_toplevels)) => {
+                        workspace_toplevels = config.arrange_toplevels(&ws_toplevels);
+                        for toplevels in workspace_toplevels.values_mut() {
+                            toplevels.retain(|toplevel| config.shows_in_panel(toplevel));
+                        }
+                    }
+                    Some(WaylandEvent::ActiveToplevelChanged { old, new }) => {
+                        wayland_subscription::apply_active_change(
+                            workspace_toplevels.values_mut().flatten(),
+                            old.as_ref(),
+                            new.as_ref(),
+                        );
+                    }
+                    Some(_) => {}
+                    None => return,
+                },
+                _ = ticks.tick() => {
+                    let snapshot = Snapshot::new(&workspaces, &workspace_toplevels);
+                    let line = match serde_json::to_string(&blocks(&snapshot)) {
+                        Ok(line) => line,
+                        Err(err) => {
+                            log::error!("failed to serialize status line: {err}");
+                            continue;
+                        }
+                    };
+                    if writeln!(stdout, "{line},").and_then(|()| stdout.flush()).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// One block per workspace, e.g. "2: firefox code"
+fn blocks(snapshot: &Snapshot) -> Vec<Block> {
+    snapshot
+        .workspaces
+        .iter()
+        .map(|workspace| {
+            let mut full_text = workspace.name.clone();
+            if !workspace.windows.is_empty() {
+                full_text.push(':');
+                for window in &workspace.windows {
+                    full_text.push(' ');
+                    full_text.push_str(&window.app_id);
+                }
+            }
+            Block {
+                name: "workspace",
+                instance: workspace.name.clone(),
+                full_text,
+                color: if workspace.is_active {
+                    "#ffffff"
+                } else {
+                    "#888888"
+                },
+            }
+        })
+        .collect()
+}
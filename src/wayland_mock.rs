@@ -0,0 +1,177 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Synthetic workspaces and windows for working on the UI without a COSMIC session, e.g. for
+//! consistent screenshots. Enabled by setting [`MOCK_VAR`].
+//!
+//! Handles are Wayland proxies that can only be created through a connection. They are
+//! created on a connection to one end of a socket pair that nothing serves, so they are
+//! distinct but the compositor requests the app sends for them have no effect.
+
+use std::collections::HashMap;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+
+use cosmic::cctk::wayland_client::protocol::wl_registry::WlRegistry;
+use cosmic::cctk::wayland_client::{
+    delegate_noop, Connection, Dispatch, EventQueue, Proxy, QueueHandle,
+};
+use cosmic::cctk::wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1;
+use wayland_protocols::ext::workspace::v1::client::ext_workspace_handle_v1::ExtWorkspaceHandleV1;
+
+use crate::wayland_subscription::{AppToplevel, AppWorkspace, WaylandEvent};
+
+/// Environment variable that replaces the compositor connection with synthetic state.
+pub const MOCK_VAR: &str = "WORKSPACE_APPS_MOCK";
+
+/// Workspace names, the first one is active.
+const WORKSPACES: [&str; 4] = ["1", "2", "3", "4"];
+
+/// Windows as (workspace index, app id, title, minimized), the first one is focused.
+const TOPLEVELS: [(usize, &str, &str, bool); 5] = [
+    (
+        0,
+        "firefox",
+        "COSMIC Desktop Environment - Mozilla Firefox",
+        false,
+    ),
+    (0, "com.system76.CosmicFiles", "Home", false),
+    (
+        1,
+        "com.system76.CosmicTerm",
+        "~/src/cosmic-applet-workspace-apps",
+        false,
+    ),
+    (2, "com.system76.CosmicEdit", "main.rs", true),
+    (2, "", "Untitled window", false),
+];
+
+struct MockState;
+
+delegate_noop!(MockState: ignore WlRegistry);
+delegate_noop!(MockState: ignore ExtWorkspaceHandleV1);
+delegate_noop!(MockState: ignore ExtForeignToplevelHandleV1);
+
+/// Creates workspaces and windows with distinct handles. The handles stay live objects for as
+/// long as this is kept.
+pub struct Handles {
+    registry: WlRegistry,
+    qh: QueueHandle<MockState>,
+    _queue: EventQueue<MockState>,
+    _conn: Connection,
+    _server: UnixStream,
+}
+
+impl Handles {
+    /// Connects to one end of a new socket pair, `None` if that fails.
+    pub fn new() -> Option<Self> {
+        let (client, server) = match UnixStream::pair() {
+            Ok(pair) => pair,
+            Err(err) => {
+                log::error!("mock state unavailable - failed to create a socket pair: {err}");
+                return None;
+            }
+        };
+        let conn = match Connection::from_socket(client) {
+            Ok(conn) => conn,
+            Err(err) => {
+                log::error!("mock state unavailable - failed to create a connection: {err}");
+                return None;
+            }
+        };
+        let queue = conn.new_event_queue::<MockState>();
+        let qh = queue.handle();
+        let registry = conn.display().get_registry(&qh, ());
+        Some(Self {
+            registry,
+            qh,
+            _queue: queue,
+            _conn: conn,
+            _server: server,
+        })
+    }
+
+    /// An inactive workspace on the panel's output.
+    pub fn workspace(&self, name: &str, coordinates: Vec<u32>) -> AppWorkspace {
+        AppWorkspace {
+            handle: self.handle(),
+            name: name.to_string(),
+            is_active: false,
+            on_panel_output: true,
+            group: None,
+            coordinates,
+        }
+    }
+
+    /// An unfocused window on `workspace` without a title or known geometry. Its identifier
+    /// is unique among the handles' windows.
+    pub fn toplevel(&self, workspace: &AppWorkspace, app_id: &str) -> AppToplevel {
+        let handle: ExtForeignToplevelHandleV1 = self.handle();
+        AppToplevel {
+            identifier: format!("mock-{}", handle.id().protocol_id()),
+            handle,
+            cosmic_handle: None,
+            app_id: app_id.to_string(),
+            title: String::new(),
+            is_active: false,
+            is_minimized: false,
+            is_maximized: false,
+            is_fullscreen: false,
+            ws_handle: workspace.handle.clone(),
+            ws_handles: vec![workspace.handle.clone()],
+            coordinates: None,
+            sequence: 0,
+        }
+    }
+
+    /// A new, distinct handle. Binding creates an object of any interface client side, the
+    /// request never reaches a compositor.
+    fn handle<I: Proxy + 'static>(&self) -> I
+    where
+        MockState: Dispatch<I, ()>,
+    {
+        self.registry.bind(0, 1, &self.qh, ())
+    }
+}
+
+/// The events a compositor with the mock state would send on connecting, empty if the
+/// handles can't be created.
+pub fn events() -> Vec<WaylandEvent> {
+    let Some(handles) = Handles::new() else {
+        return Vec::new();
+    };
+
+    let workspaces: Vec<AppWorkspace> = WORKSPACES
+        .iter()
+        .enumerate()
+        .map(|(index, name)| AppWorkspace {
+            is_active: index == 0,
+            ..handles.workspace(name, vec![index as u32])
+        })
+        .collect();
+
+    let mut toplevels: HashMap<_, HashMap<_, _>> = HashMap::new();
+    for (index, (ws_index, app_id, title, is_minimized)) in TOPLEVELS.into_iter().enumerate() {
+        let workspace = &workspaces[ws_index];
+        let toplevel = AppToplevel {
+            identifier: format!("mock-{index}"),
+            title: title.to_string(),
+            is_active: index == 0,
+            is_minimized,
+            coordinates: Some((index as i32 * 100, 0)),
+            sequence: index as u64 + 1,
+            ..handles.toplevel(workspace, app_id)
+        };
+        toplevels
+            .entry(workspace.handle.clone())
+            .or_default()
+            .insert(toplevel.handle.clone(), toplevel);
+    }
+
+    // Kept for as long as the applet runs, so the handles stay live objects
+    std::mem::forget(handles);
+
+    vec![
+        WaylandEvent::WorkspacesChanged(workspaces),
+        WaylandEvent::ToplevelsUpdated(Arc::new(toplevels)),
+    ]
+}
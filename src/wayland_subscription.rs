@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use cosmic::cctk::cosmic_protocols::toplevel_info::v1::client::zcosmic_toplevel_handle_v1;
+use cosmic::cctk::cosmic_protocols::toplevel_management::v1::client::zcosmic_toplevel_manager_v1;
 use cosmic::cctk::wayland_client::Proxy;
 use cosmic::cctk::wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1;
 use cosmic::cctk::workspace::Workspace;
@@ -10,28 +11,103 @@ use cosmic::cctk::{
         self,
         output::{OutputHandler, OutputState},
         registry::{ProvidesRegistryState, RegistryState},
+        seat::{Capability, SeatHandler, SeatState},
+        reexports::{
+            calloop::{self, channel},
+            calloop_wayland_source::WaylandSource,
+        },
     },
     toplevel_info::{ToplevelInfo, ToplevelInfoHandler, ToplevelInfoState},
+    toplevel_management::{ToplevelManagerHandler, ToplevelManagerState},
     wayland_client::{
-        globals::registry_queue_init, protocol::wl_output::WlOutput,
+        globals::registry_queue_init, protocol::wl_output::WlOutput, protocol::wl_seat,
         Connection,
         QueueHandle,
+        WEnum,
     },
     workspace::{WorkspaceHandler, WorkspaceState},
 };
 use cosmic::iced;
 use futures_channel::mpsc;
 use futures_util::StreamExt;
-use std::{collections::HashMap, thread};
+use std::{collections::HashMap, sync::Arc, thread, time::Duration};
+use wayland_protocols::ext::workspace::v1::client::ext_workspace_group_handle_v1::ExtWorkspaceGroupHandleV1;
 use wayland_protocols::ext::workspace::v1::client::ext_workspace_handle_v1;
 use wayland_protocols::ext::workspace::v1::client::ext_workspace_handle_v1::ExtWorkspaceHandleV1;
 
+use crate::config::{GroupOutputFilter, UnassignedWindows};
+use crate::workspace_model::WorkspaceModel;
+
+/// Window updates within this interval, e.g. while a window is dragged, are coalesced into a
+/// single [`WaylandEvent::ToplevelsUpdated`].
+const TOPLEVELS_DEBOUNCE: Duration = Duration::from_millis(16);
+
 #[derive(Clone, Debug)]
 pub enum WaylandEvent {
+    /// The Wayland thread is running and accepts requests through the given sender.
+    Connected(WaylandSender),
     WorkspacesChanged(Vec<AppWorkspace>),
+    /// The toplevels of the shown workspaces, shared with the Wayland thread's copy of what it
+    /// sent last rather than cloned
     ToplevelsUpdated(
-        HashMap<ExtWorkspaceHandleV1, HashMap<ExtForeignToplevelHandleV1, AppToplevel>>,
+        Arc<HashMap<ExtWorkspaceHandleV1, HashMap<ExtForeignToplevelHandleV1, AppToplevel>>>,
     ),
+    /// The integer scale of the panel's output changed, 1 without a known output.
+    OutputScaleChanged(u16),
+    /// Focus moved between windows and nothing else changed, see [`apply_active_change`].
+    ActiveToplevelChanged {
+        old: Option<ExtForeignToplevelHandleV1>,
+        new: Option<ExtForeignToplevelHandleV1>,
+    },
+}
+
+/// Applies an [`WaylandEvent::ActiveToplevelChanged`] to toplevels received earlier.
+pub fn apply_active_change<'a>(
+    toplevels: impl IntoIterator<Item = &'a mut AppToplevel>,
+    old: Option<&ExtForeignToplevelHandleV1>,
+    new: Option<&ExtForeignToplevelHandleV1>,
+) {
+    for toplevel in toplevels {
+        if Some(&toplevel.handle) == new {
+            toplevel.is_active = true;
+        } else if Some(&toplevel.handle) == old {
+            toplevel.is_active = false;
+        }
+    }
+}
+
+/// Requests sent from the iced application to the Wayland thread.
+#[derive(Clone, Debug)]
+pub enum WaylandRequest {
+    ActivateWorkspace(ExtWorkspaceHandleV1),
+    ActivateToplevel(ExtForeignToplevelHandleV1),
+    /// Moves a window to another workspace
+    MoveToplevel(ExtForeignToplevelHandleV1, ExtWorkspaceHandleV1),
+    /// Changes which workspace groups are shown
+    SetGroupOutputFilter(GroupOutputFilter),
+    /// Changes where windows on no known workspace are shown, placing the current ones again
+    SetUnassignedWindows(UnassignedWindows),
+    /// Minimizes the window if `true`, restores it otherwise
+    SetMinimized(ExtForeignToplevelHandleV1, bool),
+    CloseToplevel(ExtForeignToplevelHandleV1),
+}
+
+/// Sending half of the request channel into the Wayland event loop.
+#[derive(Clone)]
+pub struct WaylandSender(channel::Sender<WaylandRequest>);
+
+impl WaylandSender {
+    pub fn send(&self, request: WaylandRequest) {
+        if let Err(err) = self.0.send(request) {
+            log::warn!("request dropped - wayland thread is gone: {err}");
+        }
+    }
+}
+
+impl std::fmt::Debug for WaylandSender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WaylandSender").finish_non_exhaustive()
+    }
 }
 
 impl AppWorkspace {
@@ -39,16 +115,34 @@ impl AppWorkspace {
         let handle = info.handle.clone();
         let name = info.name.clone();
         let is_active = info.state.contains(ext_workspace_handle_v1::State::Active);
-        let x = info.coordinates.get(0).unwrap_or(&0);
-        let y = info.coordinates.get(1).unwrap_or(&0);
-        let coordinates = (*x as i32, *y as i32);
+        let coordinates = info.coordinates.clone();
         Some(AppWorkspace {
             handle,
             name,
             is_active,
+            on_panel_output: true,
+            group: None,
             coordinates,
         })
     }
+
+    /// Orders by position in the compositor's workspace grid of any dimensionality.
+    ///
+    /// - Single-axis compositors report one linear position
+    /// - Grids are ordered row by row, comparing the last coordinate first, e.g. y before x
+    /// - Missing trailing coordinates count as 0, so a strip lines up with a grid's first row
+    /// - Workspaces without coordinates sort after all positioned ones
+    pub fn cmp_coordinates(&self, other: &Self) -> std::cmp::Ordering {
+        let (a, b) = (&self.coordinates, &other.coordinates);
+        let at = |coordinates: &[u32], axis: usize| coordinates.get(axis).copied().unwrap_or(0);
+        a.is_empty().cmp(&b.is_empty()).then_with(|| {
+            (0..a.len().max(b.len()))
+                .rev()
+                .map(|axis| at(a, axis).cmp(&at(b, axis)))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -56,47 +150,112 @@ pub struct AppWorkspace {
     pub handle: ExtWorkspaceHandleV1,
     pub name: String,
     pub is_active: bool,
-    pub coordinates: (i32, i32),
+    /// Whether the workspace's group is shown on the output this panel is on
+    pub on_panel_output: bool,
+    /// The group the workspace belongs to, at most one workspace per group is active
+    pub group: Option<ExtWorkspaceGroupHandleV1>,
+    /// Position in the compositor's workspace grid, one entry per dimension, see
+    /// [`AppWorkspace::cmp_coordinates`]
+    pub coordinates: Vec<u32>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct AppToplevel {
     pub handle: ExtForeignToplevelHandleV1,
+    /// Handle for window management requests, unset if the compositor doesn't offer
+    /// cosmic-toplevel-info
+    pub cosmic_handle: Option<zcosmic_toplevel_handle_v1::ZcosmicToplevelHandleV1>,
+    /// Compositor-assigned identifier, unique and stable for the lifetime of the window
+    pub identifier: String,
     pub app_id: String,
+    pub title: String,
     pub is_active: bool,
+    pub is_minimized: bool,
+    pub is_maximized: bool,
+    pub is_fullscreen: bool,
     pub ws_handle: ExtWorkspaceHandleV1,
-    pub coordinates: (i32, i32)
+    /// Every workspace the window is on, more than one for sticky windows. Includes
+    /// `ws_handle`, which is the last of them.
+    pub ws_handles: Vec<ExtWorkspaceHandleV1>,
+    /// Position on the panel's output, unset if the compositor reported no geometry for it
+    pub coordinates: Option<(i32, i32)>,
+    /// Order in which the windows were first seen, kept across updates
+    pub sequence: u64,
 }
 
 impl AppToplevel {
     pub fn new(
         info: &ToplevelInfo,
-        workspace: &AppWorkspace,
+        workspaces: &[AppWorkspace],
         wl_output: Option<&WlOutput>,
-    ) -> Self {
+    ) -> Option<Self> {
         let handle = info.foreign_toplevel.clone();
-        let ws_handle = workspace.handle.clone();
+        let cosmic_handle = info.cosmic_toplevel.clone();
+        let ws_handle = workspaces.last()?.handle.clone();
+        let ws_handles = workspaces.iter().map(|ws| ws.handle.clone()).collect();
+        let identifier = info.identifier.clone();
         let app_id = info.app_id.clone();
-        let coordinates = if let Some(wl_output) = wl_output {
-            let geometry = info.geometry.get(wl_output);
-            if let Some(geometry) = geometry {
-                (geometry.x, geometry.y)
-            } else {
-                (0, 0)
-            }
-        } else {
-            (0, 0)
+        let title = info.title.clone();
+        // Without a panel output, e.g. with COSMIC_PANEL_OUTPUT unset, the position on one of
+        // the window's outputs is used, the same one on every update
+        let geometry = match wl_output {
+            Some(wl_output) => info.geometry.get(wl_output),
+            None => info
+                .geometry
+                .iter()
+                .min_by_key(|(output, _)| output.id().protocol_id())
+                .map(|(_, geometry)| geometry),
         };
+        let coordinates = geometry.map(|geometry| (geometry.x, geometry.y));
         let is_active = info
             .state
             .contains(&zcosmic_toplevel_handle_v1::State::Activated);
-        AppToplevel {
+        let is_minimized = info
+            .state
+            .contains(&zcosmic_toplevel_handle_v1::State::Minimized);
+        let is_maximized = info
+            .state
+            .contains(&zcosmic_toplevel_handle_v1::State::Maximized);
+        let is_fullscreen = info
+            .state
+            .contains(&zcosmic_toplevel_handle_v1::State::Fullscreen);
+        Some(AppToplevel {
             handle,
+            cosmic_handle,
+            identifier,
             app_id,
+            title,
             ws_handle,
+            ws_handles,
             is_active,
+            is_minimized,
+            is_maximized,
+            is_fullscreen,
             coordinates,
-        }
+            // Assigned by the WorkspaceModel
+            sequence: 0,
+        })
+    }
+
+    /// Orders by position, windows with unknown geometry come after all positioned ones.
+    /// Ties are broken by [`AppToplevel::cmp_sequence`] so they don't swap between updates.
+    pub fn cmp_geometry(&self, other: &Self) -> std::cmp::Ordering {
+        let key = |toplevel: &Self| (toplevel.coordinates.is_none(), toplevel.coordinates);
+        key(self)
+            .cmp(&key(other))
+            .then_with(|| self.cmp_sequence(other))
+    }
+
+    /// Orders by when the windows were first seen, then by identifier
+    pub fn cmp_sequence(&self, other: &Self) -> std::cmp::Ordering {
+        self.sequence
+            .cmp(&other.sequence)
+            .then_with(|| self.identifier.cmp(&other.identifier))
+    }
+
+    /// Whether the window is on several workspaces at once
+    pub fn is_sticky(&self) -> bool {
+        self.ws_handles.len() > 1
     }
 }
 
@@ -107,9 +266,19 @@ impl AppToplevel {
 /// - Sets up a background thread that listens for workspace and window events
 /// - Returns a stream of WorkspaceEvent messages that can be handled by the iced application
 ///
+/// With `WORKSPACE_APPS_MOCK` set it streams the synthetic state of [`crate::wayland_mock`]
+/// instead.
+///
 /// The subscription uses a unique ID "workspace-sub" to ensure it's only created once,
 /// even if the view function is called multiple times during rendering.
 pub fn workspace_subscription() -> iced::Subscription<WaylandEvent> {
+    if std::env::var_os(crate::wayland_mock::MOCK_VAR).is_some() {
+        log::info!("showing mock workspaces instead of the compositor's");
+        return iced::Subscription::run_with_id(
+            "workspace-sub",
+            futures_util::stream::iter(crate::wayland_mock::events()),
+        );
+    }
     iced::Subscription::run_with_id(
         "workspace-sub",
         futures_util::stream::once(async {
@@ -137,20 +306,25 @@ pub struct AppData {
     output_state: OutputState,     // Tracks display/monitor information
     workspace_state: WorkspaceState, // Tracks workspace (virtual desktop) state
     toplevel_info_state: ToplevelInfoState, // Tracks window/toplevel information
-    //seat_state: SeatState,                   // Tracks input devices (keyboard, mouse)
+    toplevel_manager_state: ToplevelManagerState, // Issues window management requests
+    seat_state: SeatState,         // Tracks input devices (keyboard, mouse)
 
     // Communication channel to send events to the iced application
     sender: mpsc::Sender<WaylandEvent>,
+    // Latest toplevels update waiting for the debounce timer
+    pending_toplevels: Option<WaylandEvent>,
+    loop_handle: calloop::LoopHandle<'static, AppData>,
 
-    // Mirrored app state
-    workspaces: HashMap<ExtWorkspaceHandleV1, AppWorkspace>,
-    toplevels: HashMap<ExtForeignToplevelHandleV1, AppToplevel>,
-    workspace_toplevels:
-        HashMap<ExtWorkspaceHandleV1, HashMap<ExtForeignToplevelHandleV1, AppToplevel>>,
+    // Mirrored app state, the handlers feed it and forward the events it produces
+    model: WorkspaceModel,
 
-    // Output (monitor) filtering - which display this applet is running on
+    // Output (monitor) filtering - which display this applet is running on. An empty
+    // COSMIC_PANEL_OUTPUT means no filter, every output's workspaces are shown.
     configured_output: String, // Name from COSMIC_PANEL_OUTPUT env var
-    expected_output: Option<WlOutput>, // Resolved Wayland output object
+    expected_output: Option<WlOutput>, // Resolved Wayland output object, None shows all
+    output_scale: u16,         // Scale of expected_output as last sent
+    group_output_filter: GroupOutputFilter,
+    unassigned_windows: UnassignedWindows,
 }
 
 impl AppData {
@@ -169,96 +343,274 @@ impl AppData {
             log::debug!("toplevel_handle_id={} info not found", handle.id());
             return None;
         }
-        let ws = tl_info?
+        let mut workspaces: Vec<AppWorkspace> = tl_info?
             .workspace
             .iter()
             .filter_map(|ws_handle| self.get_workspace_from_handle(ws_handle))
-            .last();
-        if ws.is_none() {
+            .collect();
+        if workspaces.is_empty() {
+            workspaces = self.unassigned_workspaces(handle);
+            log::debug!(
+                "toplevel_id={} on no known workspace, placed by {:?} on {} workspaces",
+                tl_info?.identifier,
+                self.unassigned_windows,
+                workspaces.len()
+            );
+        }
+        let toplevel = AppToplevel::new(tl_info?, &workspaces, self.expected_output.as_ref());
+        if toplevel.is_none() {
             log::debug!(
                 "toplevel_id={} workspace info not found",
                 tl_info?.identifier
             );
-            return None;
         }
-        Some(AppToplevel::new(
-            tl_info?,
-            &ws?,
-            self.expected_output.as_ref(),
-        ))
+        toplevel
     }
 
     fn send_event(&mut self, event: WaylandEvent) {
-        let _ = self.sender.try_send(event);
+        if let WaylandEvent::ToplevelsUpdated(_) = event {
+            self.debounce_toplevels(event);
+            return;
+        }
+        // Keep the order, events sent later may refer to the pending toplevels
+        self.flush_toplevels();
+        match self.sender.try_send(event) {
+            Err(err) if err.is_full() => {
+                if let WaylandEvent::ActiveToplevelChanged { .. } = err.into_inner() {
+                    // The app's toplevels would keep the old focus, so send all of them
+                    log::debug!("focus change dropped - app is behind, resending toplevels");
+                    self.debounce_toplevels(self.model.sent_toplevels());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Sends `event` after [`TOPLEVELS_DEBOUNCE`], replacing the pending one. Each update is
+    /// a full snapshot, so only the latest one has to be sent.
+    fn debounce_toplevels(&mut self, event: WaylandEvent) {
+        if self.pending_toplevels.replace(event).is_none() {
+            let timer = calloop::timer::Timer::from_duration(TOPLEVELS_DEBOUNCE);
+            let inserted = self.loop_handle.insert_source(timer, |_, _, app_data| {
+                app_data.flush_toplevels();
+                calloop::timer::TimeoutAction::Drop
+            });
+            if let Err(err) = inserted {
+                log::warn!(
+                    "toplevels update sent undebounced - no timer: {}",
+                    err.error
+                );
+                if let Some(event) = self.pending_toplevels.take() {
+                    let _ = self.sender.try_send(event);
+                }
+            }
+        }
     }
 
-    fn get_matching_toplevel(&self, toplevel: &AppToplevel) -> Option<&AppToplevel> {
-        self.workspace_toplevels
-            .get(&toplevel.ws_handle)
-            .and_then(|ws_toplevels| ws_toplevels.get(&toplevel.handle))
+    fn flush_toplevels(&mut self) {
+        if let Some(event) = self.pending_toplevels.take() {
+            if let Err(err) = self.sender.try_send(event) {
+                if err.is_full() {
+                    log::debug!("toplevels update delayed - app is behind");
+                    self.debounce_toplevels(err.into_inner());
+                }
+            }
+        }
+    }
+
+    fn send_events(&mut self, events: Vec<WaylandEvent>) {
+        for event in events {
+            self.send_event(event);
+        }
+    }
+
+    /// Handles a request from the iced application.
+    fn handle_request(&mut self, request: WaylandRequest) {
+        match request {
+            WaylandRequest::ActivateWorkspace(handle) => {
+                handle.activate();
+                if let Ok(workspace_manager) = self.workspace_state.workspace_manager().get() {
+                    workspace_manager.commit();
+                } else {
+                    log::debug!(
+                        "workspace_handle_id={} activate ignored - no workspace manager",
+                        handle.id()
+                    );
+                }
+            }
+            WaylandRequest::ActivateToplevel(handle) => {
+                let cosmic_toplevel = self.model.cosmic_handle(&handle);
+                let seat = self.seat_state.seats().next();
+                if let (Some(cosmic_toplevel), Some(seat)) = (cosmic_toplevel, seat) {
+                    self.toplevel_manager_state
+                        .manager
+                        .activate(&cosmic_toplevel, &seat);
+                } else {
+                    log::debug!(
+                        "toplevel_handle_id={} activate ignored - no cosmic handle or seat",
+                        handle.id()
+                    );
+                }
+            }
+            WaylandRequest::SetGroupOutputFilter(filter) => {
+                if self.group_output_filter != filter {
+                    self.group_output_filter = filter;
+                    self.update_workspaces();
+                }
+            }
+            WaylandRequest::SetUnassignedWindows(unassigned_windows) => {
+                if self.unassigned_windows != unassigned_windows {
+                    self.unassigned_windows = unassigned_windows;
+                    self.place_toplevels();
+                }
+            }
+            WaylandRequest::SetMinimized(handle, minimized) => {
+                let Some(cosmic_toplevel) = self.model.cosmic_handle(&handle) else {
+                    log::debug!(
+                        "toplevel_handle_id={} minimize ignored - no cosmic handle",
+                        handle.id()
+                    );
+                    return;
+                };
+                let manager = &self.toplevel_manager_state.manager;
+                if minimized {
+                    manager.set_minimized(&cosmic_toplevel);
+                } else {
+                    manager.unset_minimized(&cosmic_toplevel);
+                }
+            }
+            WaylandRequest::CloseToplevel(handle) => {
+                if let Some(cosmic_toplevel) = self.model.cosmic_handle(&handle) {
+                    self.toplevel_manager_state.manager.close(&cosmic_toplevel);
+                } else {
+                    log::debug!(
+                        "toplevel_handle_id={} close ignored - no cosmic handle",
+                        handle.id()
+                    );
+                }
+            }
+            WaylandRequest::MoveToplevel(handle, ws_handle) => {
+                let manager = &self.toplevel_manager_state.manager;
+                // move_to_ext_workspace was added in version 4
+                if manager.version() < 4 {
+                    log::debug!(
+                        "toplevel_handle_id={} move ignored - toplevel manager too old",
+                        handle.id()
+                    );
+                    return;
+                }
+                let cosmic_toplevel = self.model.cosmic_handle(&handle);
+                let output = self
+                    .workspace_state
+                    .workspace_groups()
+                    .find(|group| group.workspaces.contains(&ws_handle))
+                    .and_then(|group| group.outputs.first().cloned());
+                if let (Some(cosmic_toplevel), Some(output)) = (cosmic_toplevel, output) {
+                    manager.move_to_ext_workspace(&cosmic_toplevel, &ws_handle, &output);
+                } else {
+                    log::debug!(
+                        "toplevel_handle_id={} move ignored - no cosmic handle or output",
+                        handle.id()
+                    );
+                }
+            }
+        }
+    }
+
+    /// Sends the scale of the panel's output if it changed since it was last sent.
+    fn update_output_scale(&mut self) {
+        let scale = self
+            .expected_output
+            .as_ref()
+            .and_then(|output| self.output_state.info(output))
+            .map_or(1, |info| {
+                u16::try_from(info.scale_factor).unwrap_or(1).max(1)
+            });
+        if scale != self.output_scale {
+            log::debug!("panel output scale changed to {scale}");
+            self.output_scale = scale;
+            self.send_event(WaylandEvent::OutputScaleChanged(scale));
+        }
+    }
+
+    /// Follows a change of `expected_output`, which decides the shown workspace groups and
+    /// the output window positions are taken from.
+    fn output_changed(&mut self) {
+        self.update_output_scale();
+        self.update_workspaces();
+        self.place_toplevels();
+    }
+
+    /// Stand-in workspaces for a window that is on no known workspace, see
+    /// [`UnassignedWindows`]
+    fn unassigned_workspaces(&self, handle: &ExtForeignToplevelHandleV1) -> Vec<AppWorkspace> {
+        let ws_handles = match self.unassigned_windows {
+            UnassignedWindows::KeepLast => self.model.workspace_handles(handle),
+            UnassignedWindows::ActiveWorkspace => {
+                self.model.active_workspace().into_iter().collect()
+            }
+            UnassignedWindows::Hide => Vec::new(),
+        };
+        ws_handles
+            .iter()
+            .filter_map(|ws_handle| self.get_workspace_from_handle(ws_handle))
+            .collect()
     }
 
     fn is_active_output(&self, output: &WlOutput) -> bool {
         self.expected_output.is_none() || Some(output) == self.expected_output.as_ref()
     }
 
-    fn add_top_level(&mut self, toplevel: AppToplevel) {
-        let ws_id = &toplevel.ws_handle;
-        let tl_id = &toplevel.handle;
-        self.remove_toplevel(tl_id);
-        let mut ws_toplevels = self
-            .workspace_toplevels
-            .get(ws_id)
-            .cloned()
-            .unwrap_or_default();
-        ws_toplevels.insert(tl_id.clone(), toplevel.clone());
-        self.workspace_toplevels.insert(ws_id.clone(), ws_toplevels);
-        self.toplevels.insert(tl_id.clone(), toplevel);
-    }
-
-    fn remove_toplevel(&mut self, handle: &ExtForeignToplevelHandleV1) -> bool {
-        if let Some(toplevel) = self.toplevels.remove(handle) {
-            let ws_id = &toplevel.ws_handle;
-            if let Some(ws_toplevels) = self.workspace_toplevels.get_mut(ws_id) {
-                ws_toplevels.remove(handle);
-                return true;
-            } else {
-                log::debug!("toplevel_id={} remove - workspace not found", handle.id());
+    /// Whether a workspace group with these outputs is shown, see [`GroupOutputFilter`]
+    fn includes_group(&self, outputs: &[WlOutput]) -> bool {
+        match self.group_output_filter {
+            GroupOutputFilter::Any => outputs.iter().any(|output| self.is_active_output(output)),
+            GroupOutputFilter::Exclusive => {
+                !outputs.is_empty() && outputs.iter().all(|output| self.is_active_output(output))
             }
-        } else {
-            log::debug!(
-                "toplevel_id={} remove ignored - toplevel not found",
-                handle.id()
-            );
         }
-        false
     }
-}
 
-/// WorkspaceHandler trait implementation.
-///
-/// This trait defines callbacks for workspace-related events from2 the compositor.
-/// The compositor uses a batching model: it sends multiple events, then calls done()
-/// to signal "all updates have been sent, now process them as a batch".
-impl WorkspaceHandler for AppData {
-    fn workspace_state(&mut self) -> &mut WorkspaceState {
-        &mut self.workspace_state
+    /// Passes the current state of a window to the model.
+    ///
+    /// A window on no known workspace, e.g. mid-move to one whose info hasn't arrived yet, is
+    /// placed according to `unassigned_windows`, and dropped if that yields no workspace. The
+    /// update completing the move puts it where it belongs.
+    fn place_toplevel(&mut self, handle: &ExtForeignToplevelHandleV1) -> Vec<WaylandEvent> {
+        match self.get_toplevel_from_handle(handle) {
+            Some(toplevel) => self.model.update_toplevel(toplevel),
+            None => self.model.remove_toplevel(handle),
+        }
     }
 
-    /// Called when the compositor has finished sending all workspace state updates.
-    /// This is where we process the accumulated changes and send them to the app.
-    fn done(&mut self) {
+    /// Places every window again, e.g. after `unassigned_windows` changed.
+    fn place_toplevels(&mut self) {
+        let handles: Vec<ExtForeignToplevelHandleV1> = self
+            .toplevel_info_state
+            .toplevels()
+            .map(|info| info.foreign_toplevel.clone())
+            .collect();
+        for handle in handles {
+            let events = self.place_toplevel(&handle);
+            self.send_events(events);
+        }
+    }
+
+    /// Collects the workspaces of the shown groups and passes them to the model.
+    fn update_workspaces(&mut self) {
         let mut new_state = HashMap::new();
         for group in self.workspace_state.workspace_groups() {
-            let include = group
-                .outputs
-                .iter()
-                .any(|output| self.is_active_output(output));
-            if !include {
+            if !self.includes_group(&group.outputs) {
                 continue;
             }
+            let on_panel_output = self
+                .expected_output
+                .as_ref()
+                .is_none_or(|expected| group.outputs.contains(expected));
             for workspace_handle in &group.workspaces {
-                if let Some(ws) = self.get_workspace_from_handle(workspace_handle) {
+                if let Some(mut ws) = self.get_workspace_from_handle(workspace_handle) {
+                    ws.on_panel_output = on_panel_output;
+                    ws.group = Some(group.handle.clone());
                     new_state.insert(ws.handle.clone(), ws);
                 } else {
                     log::debug!(
@@ -268,24 +620,25 @@ impl WorkspaceHandler for AppData {
                 }
             }
         }
-        let old_state = &self.workspaces;
-        if *old_state == new_state {
-            return;
-        }
+        let events = self.model.set_workspaces(new_state);
+        self.send_events(events);
+    }
+}
 
-        let removed_keys = old_state
-            .keys()
-            .filter(|&k| !new_state.contains_key(k))
-            .cloned()
-            .collect::<Vec<_>>();
-        for key in removed_keys {
-            self.workspace_toplevels.remove(&key);
-        }
+/// WorkspaceHandler trait implementation.
+///
+/// This trait defines callbacks for workspace-related events from2 the compositor.
+/// The compositor uses a batching model: it sends multiple events, then calls done()
+/// to signal "all updates have been sent, now process them as a batch".
+impl WorkspaceHandler for AppData {
+    fn workspace_state(&mut self) -> &mut WorkspaceState {
+        &mut self.workspace_state
+    }
 
-        self.workspaces = new_state;
-        let mut workspaces_vec = self.workspaces.values().cloned().collect::<Vec<_>>();
-        workspaces_vec.sort_by_key(|ws| ws.name.clone());
-        self.send_event(WaylandEvent::WorkspacesChanged(workspaces_vec));
+    /// Called when the compositor has finished sending all workspace state updates.
+    /// This is where we process the accumulated changes and send them to the app.
+    fn done(&mut self) {
+        self.update_workspaces();
     }
 }
 
@@ -293,7 +646,9 @@ impl WorkspaceHandler for AppData {
 ///
 /// This trait defines callbacks for window/toplevel-related events.
 /// A "toplevel" is a top-level window (not a popup or subsurface).
-/// In COSMIC, stacked/tabbed windows appear as a single toplevel.
+/// In COSMIC, stacked/tabbed windows appear as a single toplevel. Neither
+/// ext-foreign-toplevel-list nor cosmic-toplevel-info report stack membership or the
+/// number of tabs, so the applet can't tell a stack from a single window.
 impl ToplevelInfoHandler for AppData {
     fn toplevel_info_state(&mut self) -> &mut ToplevelInfoState {
         &mut self.toplevel_info_state
@@ -307,10 +662,8 @@ impl ToplevelInfoHandler for AppData {
         handle: &ExtForeignToplevelHandleV1,
     ) {
         if let Some(tl) = self.get_toplevel_from_handle(handle) {
-            self.add_top_level(tl);
-            self.send_event(WaylandEvent::ToplevelsUpdated(
-                self.workspace_toplevels.clone(),
-            ));
+            let events = self.model.add_toplevel(tl);
+            self.send_events(events);
         } else {
             log::debug!(
                 "toplevel_handle_id={} ignored - could not retrieve toplevel info from handle",
@@ -326,24 +679,8 @@ impl ToplevelInfoHandler for AppData {
         _qh: &QueueHandle<Self>,
         toplevel: &ExtForeignToplevelHandleV1,
     ) {
-        if let Some(new_app_toplevel) = self.get_toplevel_from_handle(toplevel) {
-            let old_app_toplevel = self.get_matching_toplevel(&new_app_toplevel);
-            let equals = old_app_toplevel
-                .map(|old_app_top_level| *old_app_top_level == new_app_toplevel)
-                .unwrap_or(false);
-            if !equals {
-                self.add_top_level(new_app_toplevel);
-                self.send_event(WaylandEvent::ToplevelsUpdated(
-                    self.workspace_toplevels.clone(),
-                ));
-            } else {
-                log::debug!(
-                    "toplevel_id={}, app_id={} update ignored - no changes detected",
-                    new_app_toplevel.handle.id(),
-                    new_app_toplevel.app_id
-                );
-            }
-        }
+        let events = self.place_toplevel(toplevel);
+        self.send_events(events);
     }
 
     /// Called when a toplevel window is closed/destroyed.
@@ -353,21 +690,9 @@ impl ToplevelInfoHandler for AppData {
         _qh: &QueueHandle<Self>,
         handle: &ExtForeignToplevelHandleV1,
     ) {
-        let tl = self.get_toplevel_from_handle(handle);
-        if let Some(toplevel) = tl {
-            let tl_id = toplevel.handle;
-            let removed = self.remove_toplevel(&tl_id);
-            if removed {
-                self.send_event(WaylandEvent::ToplevelsUpdated(
-                    self.workspace_toplevels.clone(),
-                ));
-            }
-        } else {
-            log::debug!(
-                "toplevel_handle_id={} close ignored - could not retrieve toplevel info from handle",
-                handle.id()
-            );
-        }
+        // The model remembers the toplevel, so its info isn't needed anymore
+        let events = self.model.close_toplevel(handle);
+        self.send_events(events);
     }
 }
 
@@ -376,7 +701,23 @@ impl ProvidesRegistryState for AppData {
         &mut self.registry_state
     }
 
-    sctk::registry_handlers![OutputState,];
+    sctk::registry_handlers![OutputState, SeatState,];
+}
+
+/// The output named `configured_output` among `outputs` and their names. `None` means no
+/// output filter: without a configured output, e.g. when run outside of the panel, every
+/// output is shown, and a configured output that isn't there yet is picked up on arrival.
+fn panel_output<T>(
+    outputs: impl IntoIterator<Item = (T, Option<String>)>,
+    configured_output: &str,
+) -> Option<T> {
+    if configured_output.is_empty() {
+        return None;
+    }
+    outputs
+        .into_iter()
+        .find(|(_, name)| name.as_deref() == Some(configured_output))
+        .map(|(output, _)| output)
 }
 
 impl OutputHandler for AppData {
@@ -385,21 +726,50 @@ impl OutputHandler for AppData {
     }
 
     fn new_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, output: WlOutput) {
-        let info = self.output_state.info(&output).unwrap();
-        if info.name.as_deref() == Some(&self.configured_output) {
+        let Some(info) = self.output_state.info(&output) else {
+            log::warn!("output_id={} ignored - no output info", output.id());
+            return;
+        };
+        if let Some(output) = panel_output([(output, info.name)], &self.configured_output) {
             self.expected_output = Some(output);
+            self.output_changed();
         }
     }
 
-    fn update_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {
-        log::info!("Hello");
+    fn update_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, output: WlOutput) {
+        let name = self.output_state.info(&output).and_then(|info| info.name);
+        log::debug!("output_id={} updated, name={name:?}", output.id());
+        if self.expected_output.as_ref() == Some(&output) {
+            self.update_output_scale();
+        }
     }
 
-    fn output_destroyed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {
-        log::info!("Hello")
+    fn output_destroyed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, output: WlOutput) {
+        if self.expected_output.as_ref() == Some(&output) {
+            log::info!("output_id={} of the panel destroyed", output.id());
+            self.expected_output = None;
+            self.output_changed();
+        } else {
+            log::debug!("output_id={} destroyed", output.id());
+        }
     }
 }
-/*
+impl ToplevelManagerHandler for AppData {
+    fn toplevel_manager_state(&mut self) -> &mut ToplevelManagerState {
+        &mut self.toplevel_manager_state
+    }
+
+    fn capabilities(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _capabilities: Vec<
+            WEnum<zcosmic_toplevel_manager_v1::ZcosmicToplelevelManagementCapabilitiesV1>,
+        >,
+    ) {
+    }
+}
+
 impl SeatHandler for AppData {
     fn seat_state(&mut self) -> &mut SeatState {
         &mut self.seat_state
@@ -411,7 +781,7 @@ impl SeatHandler for AppData {
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
         _seat: wl_seat::WlSeat,
-        _capability: sctk::seat::Capability,
+        _capability: Capability,
     ) {
     }
     fn remove_capability(
@@ -419,13 +789,12 @@ impl SeatHandler for AppData {
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
         _seat: wl_seat::WlSeat,
-        _capability: sctk::seat::Capability,
+        _capability: Capability,
     ) {
     }
     fn remove_seat(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _seat: wl_seat::WlSeat) {
     }
 }
-*/
 // Delegate macros: These generate boilerplate code to wire up Wayland event dispatching.
 //
 // The Wayland protocol works by having the compositor send events over a socket.
@@ -436,8 +805,9 @@ impl SeatHandler for AppData {
 // protocol interface, routing events to the appropriate handler methods.
 cctk::delegate_workspace!(AppData); // Routes workspace events to WorkspaceHandler methods
 cctk::delegate_toplevel_info!(AppData); // Routes toplevel events to ToplevelInfoHandler methods
+cctk::delegate_toplevel_manager!(AppData); // Routes toplevel management events to ToplevelManagerHandler methods
 sctk::delegate_output!(AppData); // Routes output (monitor) events to OutputHandler methods
-//sctk::delegate_seat!(AppData);            // Routes seat (input device) events to SeatHandler methods
+sctk::delegate_seat!(AppData); // Routes seat (input device) events to SeatHandler methods
 sctk::delegate_registry!(AppData); // Routes registry (global discovery) events
 
 /// Starts the Wayland event loop in a background thread.
@@ -450,14 +820,21 @@ sctk::delegate_registry!(AppData); // Routes registry (global discovery) events
 /// The background thread:
 /// - Connects to the Wayland compositor's global registry
 /// - Binds to the workspace and toplevel info protocols
-/// - Enters an infinite loop that processes Wayland events
+/// - Enters an infinite loop that processes Wayland events and requests from the application
 /// - When events occur, they're handled by the trait implementations and sent via the channel
-async fn start(conn: Connection) -> mpsc::Receiver<WaylandEvent> {
+pub async fn start(conn: Connection) -> mpsc::Receiver<WaylandEvent> {
     let (sender, receiver) = mpsc::channel(16);
 
     thread::spawn(move || {
         // Initialize the Wayland event queue and discover available global objects
-        let (globals, mut event_queue) = registry_queue_init(&conn).unwrap();
+        // On failure the sender is dropped, which ends the stream like a failed connection
+        let (globals, event_queue) = match registry_queue_init(&conn) {
+            Ok(registry) => registry,
+            Err(err) => {
+                log::error!("failed to initialize wayland registry: {err}");
+                return;
+            }
+        };
         let qh = event_queue.handle();
 
         // Check which monitor/output this applet instance is running on
@@ -471,47 +848,226 @@ async fn start(conn: Connection) -> mpsc::Receiver<WaylandEvent> {
         let output_state = OutputState::new(&globals, &qh);
         let workspace_state = WorkspaceState::new(&registry_state, &qh);
         let toplevel_info_state = ToplevelInfoState::new(&registry_state, &qh);
-        //let seat_state = SeatState::new(&globals, &qh);
+        let toplevel_manager_state = ToplevelManagerState::new(&registry_state, &qh);
+        let seat_state = SeatState::new(&globals, &qh);
+
+        // The event loop multiplexes Wayland events and requests from the application,
+        // which arrive through a calloop channel handed to the app via WaylandEvent::Connected
+        let mut event_loop = match calloop::EventLoop::<AppData>::try_new() {
+            Ok(event_loop) => event_loop,
+            Err(err) => {
+                log::error!("failed to create wayland event loop: {err}");
+                return;
+            }
+        };
+        let loop_handle = event_loop.handle();
 
         let mut app_data = AppData {
             registry_state,
             output_state,
             workspace_state,
             toplevel_info_state,
-            //seat_state,
+            toplevel_manager_state,
+            seat_state,
             sender,
-            toplevels: HashMap::new(),
-            workspace_toplevels: HashMap::new(),
-            workspaces: HashMap::new(),
+            pending_toplevels: None,
+            loop_handle: loop_handle.clone(),
+            model: WorkspaceModel::default(),
             configured_output: configured_output.clone(),
             expected_output: None,
+            output_scale: 1,
+            group_output_filter: GroupOutputFilter::default(),
+            unassigned_windows: UnassignedWindows::default(),
         };
 
         // Check for existing outputs that match the configured output
-        // If no specific output is configured, use the first available output
-        for output in app_data.output_state.outputs() {
-            if let Some(info) = app_data.output_state.info(&output) {
-                if configured_output.is_empty() || info.name.as_deref() == Some(&configured_output)
-                {
-                    app_data.expected_output = Some(output.clone());
-                    break;
-                }
+        let outputs = app_data.output_state.outputs().map(|output| {
+            let name = app_data
+                .output_state
+                .info(&output)
+                .and_then(|info| info.name);
+            (output, name)
+        });
+        app_data.expected_output = panel_output(outputs, &configured_output);
+
+        if let Err(err) = WaylandSource::new(conn, event_queue).insert(loop_handle.clone()) {
+            log::error!(
+                "failed to insert wayland source into event loop: {}",
+                err.error
+            );
+            return;
+        }
+        let (request_sender, request_channel) = channel::channel();
+        let inserted = loop_handle.insert_source(request_channel, |event, _, app_data| {
+            if let channel::Event::Msg(request) = event {
+                app_data.handle_request(request);
             }
+        });
+        if let Err(err) = inserted {
+            log::error!(
+                "failed to insert request channel into event loop: {}",
+                err.error
+            );
+            return;
         }
+        app_data.send_event(WaylandEvent::Connected(WaylandSender(request_sender)));
+        app_data.update_output_scale();
+        log::debug!(
+            "wayland event loop started, configured_output={configured_output:?}, \
+             expected_output={:?}",
+            app_data.expected_output.as_ref().map(|output| output.id())
+        );
 
         // Main event loop: waits for events from compositor and dispatches to handlers
-        // blocking_dispatch() blocks until events arrive, then calls the appropriate
+        // dispatch() blocks until events arrive, then calls the appropriate
         // handler methods on app_data based on the delegate macros above
         loop {
-            event_queue
-                .blocking_dispatch(&mut app_data)
-                .unwrap_or_else(|err| {
-                    // TODO: Handle Wayland disconnection gracefully
-                    eprintln!("Wayland event dispatch error: {:?}", err);
-                    0
-                });
+            if let Err(err) = event_loop.dispatch(None, &mut app_data) {
+                // Dispatch keeps failing once the connection is gone, ending the thread drops
+                // the sender, which ends the stream
+                log::error!("wayland event dispatch failed, stopping: {err:?}");
+                break;
+            }
         }
     });
 
     receiver
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wayland_mock::Handles;
+
+    /// Identifiers of `toplevels` sorted by [`AppToplevel::cmp_geometry`]
+    fn geometry_order(toplevels: &[AppToplevel]) -> Vec<String> {
+        let mut sorted = toplevels.to_vec();
+        sorted.sort_by(AppToplevel::cmp_geometry);
+        sorted
+            .into_iter()
+            .map(|toplevel| toplevel.identifier)
+            .collect()
+    }
+
+    /// Names of workspaces with the given coordinates, sorted by
+    /// [`AppWorkspace::cmp_coordinates`]
+    fn coordinate_order(coordinates: &[(&str, &[u32])]) -> Vec<String> {
+        let handles = Handles::new().expect("mock handles");
+        let mut workspaces: Vec<AppWorkspace> = coordinates
+            .iter()
+            .map(|(name, coordinates)| handles.workspace(name, coordinates.to_vec()))
+            .collect();
+        workspaces.sort_by(AppWorkspace::cmp_coordinates);
+        workspaces
+            .into_iter()
+            .map(|workspace| workspace.name)
+            .collect()
+    }
+
+    #[test]
+    fn panel_output_is_picked_by_the_configured_name() {
+        let outputs = || {
+            [
+                (1, Some("DP-1".to_string())),
+                (2, None),
+                (3, Some("HDMI-A-1".to_string())),
+            ]
+        };
+        assert_eq!(panel_output(outputs(), ""), None);
+        assert_eq!(panel_output(outputs(), "HDMI-A-1"), Some(3));
+        assert_eq!(panel_output(outputs(), "DP-2"), None);
+        assert_eq!(panel_output(Vec::<(u32, _)>::new(), "DP-1"), None);
+    }
+
+    #[test]
+    fn single_axis_coordinates_order_linearly() {
+        let order = coordinate_order(&[("3", &[2]), ("1", &[0]), ("10", &[9]), ("2", &[1])]);
+        assert_eq!(order, ["1", "2", "3", "10"]);
+    }
+
+    #[test]
+    fn strip_coordinates_order_along_the_strip() {
+        let order = coordinate_order(&[("c", &[2, 0]), ("a", &[0, 0]), ("b", &[1, 0])]);
+        assert_eq!(order, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn grid_coordinates_order_row_by_row() {
+        let order = coordinate_order(&[
+            ("row 1 col 1", &[1, 1]),
+            ("row 0 col 1", &[1, 0]),
+            ("row 1 col 0", &[0, 1]),
+            ("row 0 col 0", &[0, 0]),
+            ("row 0 col 2", &[2, 0]),
+        ]);
+        let expected = [
+            "row 0 col 0",
+            "row 0 col 1",
+            "row 0 col 2",
+            "row 1 col 0",
+            "row 1 col 1",
+        ];
+        assert_eq!(order, expected);
+    }
+
+    #[test]
+    fn missing_coordinates_count_as_zero() {
+        let order = coordinate_order(&[
+            ("grid second row", &[0, 1]),
+            ("strip 1", &[1]),
+            ("grid first row", &[2, 0]),
+            ("strip 0", &[0]),
+            ("cube", &[0, 0, 1]),
+        ]);
+        assert_eq!(
+            order,
+            [
+                "strip 0",
+                "strip 1",
+                "grid first row",
+                "grid second row",
+                "cube"
+            ]
+        );
+    }
+
+    #[test]
+    fn workspaces_without_coordinates_sort_last() {
+        let order = coordinate_order(&[("none", &[]), ("far", &[99, 99]), ("origin", &[0])]);
+        assert_eq!(order, ["origin", "far", "none"]);
+    }
+
+    #[test]
+    fn windows_at_the_same_or_unknown_position_keep_their_order() {
+        let handles = Handles::new().expect("mock handles");
+        let workspace = handles.workspace("1", vec![0]);
+        let toplevel = |identifier: &str, coordinates, sequence| AppToplevel {
+            identifier: identifier.to_string(),
+            coordinates,
+            sequence,
+            ..handles.toplevel(&workspace, "app")
+        };
+        let toplevels = [
+            toplevel("unknown-late", None, 5),
+            toplevel("origin-late", Some((0, 0)), 4),
+            toplevel("unknown-early", None, 1),
+            toplevel("origin-early", Some((0, 0)), 2),
+            toplevel("right", Some((10, 0)), 3),
+            // Equal sequences fall back to the identifier
+            toplevel("origin-b", Some((0, 0)), 2),
+        ];
+        let expected = [
+            "origin-b",
+            "origin-early",
+            "origin-late",
+            "right",
+            "unknown-early",
+            "unknown-late",
+        ];
+        assert_eq!(geometry_order(&toplevels), expected);
+
+        let mut reversed = toplevels.to_vec();
+        reversed.reverse();
+        assert_eq!(geometry_order(&reversed), expected);
+    }
+}
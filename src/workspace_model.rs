@@ -0,0 +1,565 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! The workspace and window state mirrored from the compositor, kept apart from the Wayland
+//! handlers so its transitions only depend on the data passed in.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use cosmic::cctk::cosmic_protocols::toplevel_info::v1::client::zcosmic_toplevel_handle_v1::ZcosmicToplevelHandleV1;
+use cosmic::cctk::wayland_client::Proxy;
+use cosmic::cctk::wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1;
+use wayland_protocols::ext::workspace::v1::client::ext_workspace_handle_v1::ExtWorkspaceHandleV1;
+
+use crate::wayland_subscription::{AppToplevel, AppWorkspace, WaylandEvent, apply_active_change};
+
+type WorkspaceToplevels =
+    HashMap<ExtWorkspaceHandleV1, HashMap<ExtForeignToplevelHandleV1, AppToplevel>>;
+
+/// Each method applies one compositor update and returns the events the app needs to see
+/// to stay in sync, empty when nothing visible changed.
+#[derive(Debug, Default)]
+pub struct WorkspaceModel {
+    /// Workspaces on the outputs this panel shows
+    workspaces: HashMap<ExtWorkspaceHandleV1, AppWorkspace>,
+    toplevels: HashMap<ExtForeignToplevelHandleV1, AppToplevel>,
+    workspace_toplevels: WorkspaceToplevels,
+    /// Toplevels as last sent to the app, used to skip redundant updates
+    sent_toplevels: Arc<WorkspaceToplevels>,
+    /// The focused window as last sent to the app
+    active_toplevel: Option<ExtForeignToplevelHandleV1>,
+    /// Sequence number of the next window seen for the first time
+    next_sequence: u64,
+    /// Sequence numbers of open windows that were removed, e.g. while on no known workspace
+    /// mid-move, so they keep their place when they come back
+    removed_sequences: HashMap<ExtForeignToplevelHandleV1, u64>,
+}
+
+impl WorkspaceModel {
+    /// Replaces the shown workspaces after the compositor finished a batch of workspace
+    /// updates.
+    pub fn set_workspaces(
+        &mut self,
+        workspaces: HashMap<ExtWorkspaceHandleV1, AppWorkspace>,
+    ) -> Vec<WaylandEvent> {
+        if self.workspaces == workspaces {
+            return Vec::new();
+        }
+        // Windows of workspaces that are no longer shown stay tracked, the workspace may come
+        // back, e.g. when its group moves back to this panel's output. Dropping them here would
+        // leave them in `toplevels` without being listed on any workspace.
+        self.workspaces = workspaces;
+
+        let mut workspaces_vec = self.workspaces.values().cloned().collect::<Vec<_>>();
+        workspaces_vec.sort_by_key(|ws| ws.name.clone());
+        let mut events = vec![WaylandEvent::WorkspacesChanged(workspaces_vec)];
+        // Windows on workspaces that appeared or disappeared change visibility
+        events.extend(self.toplevels_changed());
+        events
+    }
+
+    pub fn add_toplevel(&mut self, mut toplevel: AppToplevel) -> Vec<WaylandEvent> {
+        toplevel.sequence = self.sequence_of(&toplevel.handle);
+        self.insert_toplevel(toplevel);
+        self.toplevels_changed().into_iter().collect()
+    }
+
+    pub fn update_toplevel(&mut self, mut toplevel: AppToplevel) -> Vec<WaylandEvent> {
+        toplevel.sequence = self.sequence_of(&toplevel.handle);
+        let old = self.toplevels.get(&toplevel.handle);
+        if old == Some(&toplevel) {
+            log::debug!(
+                "toplevel_id={}, app_id={} update ignored - no changes detected",
+                toplevel.handle.id(),
+                toplevel.app_id
+            );
+            return Vec::new();
+        }
+        let only_focus_changed = old.is_some_and(|old| {
+            AppToplevel {
+                is_active: toplevel.is_active,
+                ..old.clone()
+            } == toplevel
+        });
+        let handle = toplevel.handle.clone();
+        self.insert_toplevel(toplevel);
+        if only_focus_changed {
+            self.active_changed(&handle)
+        } else {
+            self.toplevels_changed().into_iter().collect()
+        }
+    }
+
+    /// The management handle of a tracked window
+    pub fn cosmic_handle(
+        &self,
+        handle: &ExtForeignToplevelHandleV1,
+    ) -> Option<ZcosmicToplevelHandleV1> {
+        self.toplevels
+            .get(handle)
+            .and_then(|toplevel| toplevel.cosmic_handle.clone())
+    }
+
+    /// The workspaces a tracked window was last seen on
+    pub fn workspace_handles(
+        &self,
+        handle: &ExtForeignToplevelHandleV1,
+    ) -> Vec<ExtWorkspaceHandleV1> {
+        self.toplevels
+            .get(handle)
+            .map(|toplevel| toplevel.ws_handles.clone())
+            .unwrap_or_default()
+    }
+
+    /// The active workspace on the panel's output, or any active one if none is on it
+    pub fn active_workspace(&self) -> Option<ExtWorkspaceHandleV1> {
+        let active = || self.workspaces.values().filter(|ws| ws.is_active);
+        active()
+            .find(|ws| ws.on_panel_output)
+            .or_else(|| active().next())
+            .map(|ws| ws.handle.clone())
+    }
+
+    /// The toplevels as last sent, for resending them when an update didn't reach the app
+    pub fn sent_toplevels(&self) -> WaylandEvent {
+        WaylandEvent::ToplevelsUpdated(self.sent_toplevels.clone())
+    }
+
+    /// Drops a window that is still open, see [`WorkspaceModel::close_toplevel`] for closed
+    /// ones. It keeps its sequence number in case it is added again.
+    pub fn remove_toplevel(&mut self, handle: &ExtForeignToplevelHandleV1) -> Vec<WaylandEvent> {
+        if let Some(toplevel) = self.toplevels.get(handle) {
+            self.removed_sequences
+                .insert(handle.clone(), toplevel.sequence);
+        }
+        if self.take_toplevel(handle) {
+            self.toplevels_changed().into_iter().collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Drops a closed window for good
+    pub fn close_toplevel(&mut self, handle: &ExtForeignToplevelHandleV1) -> Vec<WaylandEvent> {
+        let events = self.remove_toplevel(handle);
+        self.removed_sequences.remove(handle);
+        events
+    }
+
+    /// The sequence number of a tracked or removed window, or a new one
+    fn sequence_of(&mut self, handle: &ExtForeignToplevelHandleV1) -> u64 {
+        if let Some(toplevel) = self.toplevels.get(handle) {
+            return toplevel.sequence;
+        }
+        if let Some(sequence) = self.removed_sequences.remove(handle) {
+            return sequence;
+        }
+        self.next_sequence += 1;
+        self.next_sequence
+    }
+
+    /// Adds the toplevel to each workspace it is on, after removing it from the workspaces it
+    /// was on before so a moved window doesn't stay behind on its old one
+    fn insert_toplevel(&mut self, toplevel: AppToplevel) {
+        let tl_id = &toplevel.handle;
+        self.take_toplevel(tl_id);
+        for ws_id in &toplevel.ws_handles {
+            self.workspace_toplevels
+                .entry(ws_id.clone())
+                .or_default()
+                .insert(tl_id.clone(), toplevel.clone());
+        }
+        self.toplevels.insert(tl_id.clone(), toplevel);
+    }
+
+    fn take_toplevel(&mut self, handle: &ExtForeignToplevelHandleV1) -> bool {
+        let Some(toplevel) = self.toplevels.remove(handle) else {
+            log::debug!(
+                "toplevel_id={} remove ignored - toplevel not found",
+                handle.id()
+            );
+            return false;
+        };
+        let mut removed = false;
+        for ws_id in &toplevel.ws_handles {
+            if let Some(ws_toplevels) = self.workspace_toplevels.get_mut(ws_id) {
+                removed |= ws_toplevels.remove(handle).is_some();
+                if ws_toplevels.is_empty() {
+                    self.workspace_toplevels.remove(ws_id);
+                }
+            } else {
+                log::debug!("toplevel_id={} remove - workspace not found", handle.id());
+            }
+        }
+        removed
+    }
+
+    /// The toplevels of the shown workspaces, unless they are unchanged since they were last
+    /// sent, e.g. when a window on a filtered-out output changed.
+    fn toplevels_changed(&mut self) -> Option<WaylandEvent> {
+        let visible = self.visible_toplevels();
+        if visible == *self.sent_toplevels {
+            log::debug!("toplevels update ignored - no visible changes");
+            return None;
+        }
+        self.sent_toplevels = Arc::new(visible);
+        self.active_toplevel = self.find_active_toplevel();
+        Some(WaylandEvent::ToplevelsUpdated(self.sent_toplevels.clone()))
+    }
+
+    /// Only the focus change when the focus of `changed` is all that changed, so the app can
+    /// skip rebuilding its window lists. Falls back to a full update if applying it as the app
+    /// does leaves a window different, e.g. while two windows are briefly active.
+    fn active_changed(&mut self, changed: &ExtForeignToplevelHandleV1) -> Vec<WaylandEvent> {
+        let new = self.find_active_toplevel();
+        let old = self.active_toplevel.clone();
+        // Clones the map only if the app still shares it, i.e. once after each full update
+        apply_active_change(
+            Arc::make_mut(&mut self.sent_toplevels)
+                .values_mut()
+                .flat_map(|toplevels| toplevels.values_mut()),
+            old.as_ref(),
+            new.as_ref(),
+        );
+        // Windows other than these are untouched by both the update and the change
+        let affected = [Some(changed), old.as_ref(), new.as_ref()];
+        if !affected
+            .into_iter()
+            .flatten()
+            .all(|handle| self.is_sent(handle))
+        {
+            return self.toplevels_changed().into_iter().collect();
+        }
+        if old == new {
+            return Vec::new();
+        }
+        self.active_toplevel = new.clone();
+        vec![WaylandEvent::ActiveToplevelChanged { old, new }]
+    }
+
+    /// Whether the window is as last sent on each shown workspace it is on
+    fn is_sent(&self, handle: &ExtForeignToplevelHandleV1) -> bool {
+        let Some(toplevel) = self.toplevels.get(handle) else {
+            return true;
+        };
+        toplevel
+            .ws_handles
+            .iter()
+            .filter(|ws_handle| self.workspaces.contains_key(ws_handle))
+            .all(|ws_handle| {
+                self.sent_toplevels
+                    .get(ws_handle)
+                    .and_then(|toplevels| toplevels.get(handle))
+                    == Some(toplevel)
+            })
+    }
+
+    /// Toplevels on the workspaces shown by the app
+    fn visible_toplevels(&self) -> WorkspaceToplevels {
+        self.workspace_toplevels
+            .iter()
+            .filter(|(ws_handle, _)| self.workspaces.contains_key(ws_handle))
+            .map(|(ws_handle, toplevels)| (ws_handle.clone(), toplevels.clone()))
+            .collect()
+    }
+
+    fn find_active_toplevel(&self) -> Option<ExtForeignToplevelHandleV1> {
+        self.workspace_toplevels
+            .iter()
+            .filter(|(ws_handle, _)| self.workspaces.contains_key(ws_handle))
+            .flat_map(|(_, toplevels)| toplevels.values())
+            .find(|toplevel| toplevel.is_active)
+            .map(|toplevel| toplevel.handle.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wayland_mock::Handles;
+
+    /// A model showing `workspaces`
+    fn model(workspaces: &[&AppWorkspace]) -> WorkspaceModel {
+        let mut model = WorkspaceModel::default();
+        let workspaces = workspaces
+            .iter()
+            .map(|workspace| (workspace.handle.clone(), (*workspace).clone()))
+            .collect();
+        model.set_workspaces(workspaces);
+        model
+    }
+
+    /// The toplevels sent by `events`, which have to be a single ToplevelsUpdated
+    fn sent(events: Vec<WaylandEvent>) -> Arc<WorkspaceToplevels> {
+        match events.as_slice() {
+            [WaylandEvent::ToplevelsUpdated(toplevels)] => toplevels.clone(),
+            events => panic!("expected a single ToplevelsUpdated, got {events:?}"),
+        }
+    }
+
+    #[test]
+    fn adding_a_toplevel_to_a_new_workspace_sends_it() {
+        let handles = Handles::new().expect("mock handles");
+        let workspace = handles.workspace("1", vec![0]);
+        let mut model = model(&[&workspace]);
+        let first = handles.toplevel(&workspace, "firefox");
+        let second = handles.toplevel(&workspace, "code");
+
+        let sent_first = sent(model.add_toplevel(first.clone()));
+        let shown = &sent_first[&workspace.handle];
+        assert_eq!(shown.len(), 1);
+        assert_eq!(shown[&first.handle].app_id, "firefox");
+        assert_eq!(shown[&first.handle].sequence, 1);
+
+        let sent_second = sent(model.add_toplevel(second.clone()));
+        let shown = &sent_second[&workspace.handle];
+        assert_eq!(shown.len(), 2);
+        assert_eq!(shown[&second.handle].sequence, 2);
+    }
+
+    #[test]
+    fn moving_a_toplevel_leaves_no_entry_on_the_old_workspace() {
+        let handles = Handles::new().expect("mock handles");
+        let (old, new) = (
+            handles.workspace("1", vec![0]),
+            handles.workspace("2", vec![1]),
+        );
+        let mut model = model(&[&old, &new]);
+        let toplevel = handles.toplevel(&old, "firefox");
+        model.add_toplevel(toplevel.clone());
+
+        let moved = AppToplevel {
+            ws_handle: new.handle.clone(),
+            ws_handles: vec![new.handle.clone()],
+            ..toplevel.clone()
+        };
+        let sent = sent(model.update_toplevel(moved));
+        assert!(!sent.contains_key(&old.handle));
+        assert_eq!(sent[&new.handle][&toplevel.handle].sequence, 1);
+        assert_eq!(
+            model.workspace_handles(&toplevel.handle),
+            [new.handle.clone()]
+        );
+    }
+
+    #[test]
+    fn moved_toplevels_are_dropped_from_the_old_workspace() {
+        let handles = Handles::new().expect("mock handles");
+        let (a, b, c) = (
+            handles.workspace("a", vec![0]),
+            handles.workspace("b", vec![1]),
+            handles.workspace("c", vec![2]),
+        );
+        // `a` isn't shown, the move must not depend on a sent update
+        let mut model = model(&[&b, &c]);
+        let toplevel = handles.toplevel(&a, "firefox");
+        let other = handles.toplevel(&a, "code");
+        model.add_toplevel(toplevel.clone());
+        model.add_toplevel(other.clone());
+
+        let on = |workspaces: &[&AppWorkspace]| AppToplevel {
+            ws_handle: workspaces.last().expect("a workspace").handle.clone(),
+            ws_handles: workspaces.iter().map(|ws| ws.handle.clone()).collect(),
+            ..toplevel.clone()
+        };
+        model.update_toplevel(on(&[&b]));
+        assert!(!model.workspace_toplevels[&a.handle].contains_key(&toplevel.handle));
+        assert!(model.workspace_toplevels[&a.handle].contains_key(&other.handle));
+        assert!(model.workspace_toplevels[&b.handle].contains_key(&toplevel.handle));
+
+        // Sticky on b and c, then only on c
+        model.update_toplevel(on(&[&b, &c]));
+        model.update_toplevel(on(&[&c]));
+        assert!(!model.workspace_toplevels.contains_key(&b.handle));
+        assert_eq!(model.workspace_toplevels[&c.handle].len(), 1);
+    }
+
+    #[test]
+    fn order_is_stable_across_updates_at_the_same_position() {
+        let handles = Handles::new().expect("mock handles");
+        let workspace = handles.workspace("1", vec![0]);
+        let mut model = model(&[&workspace]);
+        let toplevels: Vec<AppToplevel> = ["a", "b", "c"]
+            .into_iter()
+            .map(|app_id| AppToplevel {
+                coordinates: Some((0, 0)),
+                ..handles.toplevel(&workspace, app_id)
+            })
+            .collect();
+        for toplevel in &toplevels {
+            model.add_toplevel(toplevel.clone());
+        }
+        let order = |model: &WorkspaceModel| -> Vec<String> {
+            let mut shown: Vec<AppToplevel> = model.sent_toplevels[&workspace.handle]
+                .values()
+                .cloned()
+                .collect();
+            shown.sort_by(AppToplevel::cmp_geometry);
+            shown.into_iter().map(|toplevel| toplevel.app_id).collect()
+        };
+        assert_eq!(order(&model), ["a", "b", "c"]);
+
+        for title in ["1", "2", "3"] {
+            for toplevel in toplevels.iter().rev() {
+                model.update_toplevel(AppToplevel {
+                    title: title.to_string(),
+                    ..toplevel.clone()
+                });
+                assert_eq!(order(&model), ["a", "b", "c"]);
+            }
+        }
+
+        // Left out while on no known workspace mid-move, then back on the same position
+        model.remove_toplevel(&toplevels[0].handle);
+        assert_eq!(order(&model), ["b", "c"]);
+        model.update_toplevel(toplevels[0].clone());
+        assert_eq!(order(&model), ["a", "b", "c"]);
+
+        // A closed window's handle isn't reused, but if it were it would be a new window
+        model.close_toplevel(&toplevels[0].handle);
+        model.add_toplevel(toplevels[0].clone());
+        assert_eq!(order(&model), ["b", "c", "a"]);
+    }
+
+    /// Applies the only event of `events`, a focus change, as the app does, asserting that
+    /// `toplevels` isn't cloned for it
+    fn apply_focus_change(toplevels: &mut Arc<WorkspaceToplevels>, events: Vec<WaylandEvent>) {
+        let [WaylandEvent::ActiveToplevelChanged { old, new }] = events.as_slice() else {
+            panic!("expected only a focus change, got {events:?}");
+        };
+        let before = Arc::as_ptr(toplevels);
+        apply_active_change(
+            Arc::make_mut(toplevels)
+                .values_mut()
+                .flat_map(|toplevels| toplevels.values_mut()),
+            old.as_ref(),
+            new.as_ref(),
+        );
+        assert_eq!(Arc::as_ptr(toplevels), before, "toplevels were cloned");
+    }
+
+    /// The app applies focus changes to its copy of the toplevels with `Arc::make_mut`, which
+    /// only clones the map if the model still shares it.
+    #[test]
+    fn focus_changes_leave_the_app_the_only_owner_of_its_toplevels() {
+        let handles = Handles::new().expect("mock handles");
+        let workspaces: Vec<AppWorkspace> = (0..20)
+            .map(|index| handles.workspace(&index.to_string(), vec![index]))
+            .collect();
+        let mut model = model(&workspaces.iter().collect::<Vec<_>>());
+        let toplevels: Vec<AppToplevel> = workspaces
+            .iter()
+            .flat_map(|workspace| (0..10).map(|_| handles.toplevel(workspace, "app")))
+            .collect();
+        let mut app_toplevels = Arc::default();
+        for toplevel in &toplevels {
+            app_toplevels = sent(model.add_toplevel(toplevel.clone()));
+        }
+        assert_eq!(app_toplevels.values().map(HashMap::len).sum::<usize>(), 200);
+        // Handed over without a copy
+        assert!(Arc::ptr_eq(&app_toplevels, &model.sent_toplevels));
+
+        for toplevel in toplevels.iter().step_by(7) {
+            for is_active in [true, false] {
+                let focus = AppToplevel {
+                    is_active,
+                    ..toplevel.clone()
+                };
+                let events = model.update_toplevel(focus);
+                apply_focus_change(&mut app_toplevels, events);
+                assert_eq!(*app_toplevels, *model.sent_toplevels);
+            }
+        }
+    }
+
+    #[test]
+    fn removing_an_unknown_toplevel_sends_nothing() {
+        let handles = Handles::new().expect("mock handles");
+        let workspace = handles.workspace("1", vec![0]);
+        let mut model = model(&[&workspace]);
+        let toplevel = handles.toplevel(&workspace, "firefox");
+        model.add_toplevel(toplevel.clone());
+
+        let unknown = handles.toplevel(&workspace, "code");
+        assert!(model.remove_toplevel(&unknown.handle).is_empty());
+
+        let sent = sent(model.remove_toplevel(&toplevel.handle));
+        assert!(sent.is_empty());
+        assert!(model.remove_toplevel(&toplevel.handle).is_empty());
+    }
+
+    #[test]
+    fn unchanged_updates_send_nothing() {
+        let handles = Handles::new().expect("mock handles");
+        let workspace = handles.workspace("1", vec![0]);
+        let mut model = model(&[&workspace]);
+        let toplevel = handles.toplevel(&workspace, "firefox");
+        model.add_toplevel(toplevel.clone());
+
+        assert!(model.update_toplevel(toplevel.clone()).is_empty());
+
+        let focused = AppToplevel {
+            is_active: true,
+            ..toplevel.clone()
+        };
+        let events = model.update_toplevel(focused.clone());
+        assert!(
+            matches!(
+                events.as_slice(),
+                [WaylandEvent::ActiveToplevelChanged { old: None, new: Some(new) }]
+                    if *new == toplevel.handle
+            ),
+            "expected only a focus change, got {events:?}"
+        );
+        assert!(model.update_toplevel(focused).is_empty());
+    }
+
+    #[test]
+    fn a_second_active_window_sends_all_toplevels() {
+        let handles = Handles::new().expect("mock handles");
+        let workspace = handles.workspace("1", vec![0]);
+        let mut model = model(&[&workspace]);
+        let [first, second] = ["firefox", "code"].map(|app_id| AppToplevel {
+            is_active: true,
+            ..handles.toplevel(&workspace, app_id)
+        });
+        model.add_toplevel(first);
+        model.add_toplevel(AppToplevel {
+            is_active: false,
+            ..second.clone()
+        });
+
+        // Focus moves before the first window reports losing it
+        let toplevels = sent(model.update_toplevel(second));
+        let active = toplevels[&workspace.handle]
+            .values()
+            .filter(|toplevel| toplevel.is_active)
+            .count();
+        assert_eq!(active, 2);
+        assert_eq!(*toplevels, model.visible_toplevels());
+    }
+
+    #[test]
+    fn windows_on_workspaces_that_are_not_shown_send_no_events() {
+        let handles = Handles::new().expect("mock handles");
+        let (shown, hidden) = (
+            handles.workspace("1", vec![0]),
+            handles.workspace("2", vec![1]),
+        );
+        let mut model = model(&[&shown]);
+        let toplevel = handles.toplevel(&hidden, "firefox");
+
+        assert!(model.add_toplevel(toplevel.clone()).is_empty());
+        let renamed = AppToplevel {
+            title: "Renamed".to_string(),
+            ..toplevel.clone()
+        };
+        assert!(model.update_toplevel(renamed.clone()).is_empty());
+        let focused = AppToplevel {
+            is_active: true,
+            ..renamed
+        };
+        assert!(model.update_toplevel(focused).is_empty());
+        assert!(model.remove_toplevel(&toplevel.handle).is_empty());
+    }
+}
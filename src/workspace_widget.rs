@@ -0,0 +1,278 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Rendering of a single workspace from explicit inputs, independent of the applet's state.
+
+use cosmic::prelude::*;
+use cosmic::widget;
+
+use crate::config::{ActiveHighlight, CornerRadius};
+use crate::wayland_subscription::AppToplevel;
+
+/// Sizes and colors a workspace is rendered with.
+#[derive(Debug, Clone, Copy)]
+pub struct WorkspaceStyle {
+    /// Lay the content out as a row, or as a column for vertical panels
+    pub horizontal: bool,
+    /// Padding around the content as [vertical, horizontal]
+    pub padding: [f32; 2],
+    /// Spacing between the window icons, the gap after the name is derived from it
+    pub spacing: f32,
+    pub icon_size: u16,
+    pub text_size: u16,
+    pub show_name: bool,
+    pub show_window_count: bool,
+    pub highlight: ActiveHighlight,
+    pub border_width: f32,
+    /// Color of the highlight, the theme's accent color if `None`
+    pub active_color: Option<cosmic::iced::Color>,
+    pub radius: CornerRadius,
+    /// Opacity of the text of inactive workspaces
+    pub inactive_opacity: f32,
+}
+
+/// A workspace's state in the panel, rendered by [`WorkspaceWidget::view`].
+pub struct WorkspaceWidget<'a> {
+    /// Shown as the workspace's name, see [`crate::config::Config::workspace_label`]
+    pub label: String,
+    /// The windows shown on the workspace
+    pub toplevels: &'a [AppToplevel],
+    /// Shown before the name
+    pub icon: Option<widget::icon::Icon>,
+    /// Rendered as active, which may differ from the workspace's own state
+    pub is_active: bool,
+    /// A dragged window is over the workspace
+    pub is_drop_target: bool,
+    pub style: WorkspaceStyle,
+}
+
+impl WorkspaceWidget<'_> {
+    /// The window count shown next to the name, if enabled and there are windows
+    fn window_count(&self) -> Option<usize> {
+        (self.style.show_window_count && !self.toplevels.is_empty()).then_some(self.toplevels.len())
+    }
+
+    /// Whether the workspace would show nothing at all and is shown as a dot instead
+    fn needs_marker(&self) -> bool {
+        self.toplevels.is_empty() && !self.style.show_name && self.icon.is_none()
+    }
+
+    /// The workspace's icon, name and window count followed by `app_icons`, the already rendered
+    /// icons of its windows.
+    pub fn view<'e, M: 'e>(self, app_icons: Vec<Element<'e, M>>) -> Element<'e, M> {
+        let style = self.style;
+        let window_count = self.window_count();
+        let needs_marker = self.needs_marker();
+        let mut content: Vec<Element<'e, M>> = Vec::new();
+
+        let has_icon = self.icon.is_some();
+        if let Some(icon) = self.icon {
+            content.push(icon.size(style.icon_size).into());
+        }
+
+        if style.show_name {
+            let text = widget::text(self.label).size(style.text_size);
+            let text = if self.is_active {
+                text.font(cosmic::iced::Font {
+                    weight: cosmic::iced::font::Weight::Bold,
+                    ..Default::default()
+                })
+            } else {
+                text
+            };
+            content.push(text.into());
+        }
+
+        if let Some(count) = window_count {
+            content.push(window_count_badge(count, style.text_size));
+        }
+
+        if needs_marker {
+            // Keep empty workspaces visible and clickable without a name
+            content.push(workspace_marker(self.is_active));
+        } else if !self.toplevels.is_empty() && (style.show_name || has_icon) {
+            let gap = style.spacing + 2.0;
+            content.push(widget::Space::new(gap, gap).into());
+        }
+
+        content.extend(app_icons);
+
+        let is_active = self.is_active;
+        let is_drop_target = self.is_drop_target;
+        widget::container(flex(content, style.spacing * 0.5, style.horizontal))
+            .padding(style.padding)
+            .style(move |theme: &Theme| {
+                let cosmic = theme.cosmic();
+                let active_color = style
+                    .active_color
+                    .unwrap_or_else(|| cosmic.accent_color().into());
+                let filled = is_active && style.highlight == ActiveHighlight::Filled;
+                let background = if is_drop_target {
+                    Some(cosmic::iced::Color {
+                        a: 0.2,
+                        ..active_color
+                    })
+                } else if filled {
+                    Some(active_color)
+                } else {
+                    None
+                };
+                let text_color = if filled {
+                    cosmic.on_accent_color().into()
+                } else if is_active {
+                    cosmic.on_bg_color().into()
+                } else {
+                    cosmic::iced::Color {
+                        a: style.inactive_opacity,
+                        ..cosmic.on_bg_color().into()
+                    }
+                };
+                let show_border = is_active && style.highlight == ActiveHighlight::Border;
+                widget::container::Style {
+                    background: background.map(Into::into),
+                    text_color: Some(text_color),
+                    border: cosmic::iced_core::Border {
+                        width: if show_border { style.border_width } else { 0.0 },
+                        color: if show_border {
+                            active_color
+                        } else {
+                            cosmic::iced::Color::TRANSPARENT
+                        },
+                        radius: style.radius.radius(cosmic).into(),
+                    },
+                    ..Default::default()
+                }
+            })
+            .into()
+    }
+}
+
+/// Lays `children` out as a row if `horizontal`, as a column otherwise.
+pub fn flex<'a, M: 'a>(
+    children: Vec<Element<'a, M>>,
+    spacing: f32,
+    horizontal: bool,
+) -> Element<'a, M> {
+    if horizontal {
+        widget::row::with_children(children)
+            .spacing(spacing)
+            .align_y(cosmic::iced::Alignment::Center)
+            .into()
+    } else {
+        widget::column::with_children(children)
+            .spacing(spacing)
+            .align_x(cosmic::iced::Alignment::Center)
+            .into()
+    }
+}
+
+/// Subtle window count shown next to a workspace's name.
+fn window_count_badge<'a, M: 'a>(count: usize, text_size: u16) -> Element<'a, M> {
+    let size = (text_size as f32 * 0.75).max(8.0);
+    widget::container(widget::text(count.to_string()).size(size))
+        .style(|theme: &Theme| {
+            let cosmic = theme.cosmic();
+            widget::container::Style {
+                text_color: Some(cosmic::iced::Color {
+                    a: 0.7,
+                    ..cosmic.on_bg_color().into()
+                }),
+                ..Default::default()
+            }
+        })
+        .into()
+}
+
+/// Small dot standing in for an empty workspace, accent colored if the workspace is active.
+fn workspace_marker<'a, M: 'a>(is_active: bool) -> Element<'a, M> {
+    widget::container(widget::Space::new(6.0, 6.0))
+        .style(move |theme: &Theme| {
+            let cosmic = theme.cosmic();
+            let color: cosmic::iced::Color = if is_active {
+                cosmic.accent_color().into()
+            } else {
+                cosmic::iced::Color {
+                    a: 0.5,
+                    ..cosmic.on_bg_color().into()
+                }
+            };
+            widget::container::Style {
+                background: Some(color.into()),
+                border: cosmic::iced_core::Border {
+                    radius: cosmic.radius_xl().into(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+        })
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wayland_mock::Handles;
+
+    fn style() -> WorkspaceStyle {
+        WorkspaceStyle {
+            horizontal: true,
+            padding: [0.0, 4.0],
+            spacing: 4.0,
+            icon_size: 16,
+            text_size: 14,
+            show_name: true,
+            show_window_count: false,
+            highlight: ActiveHighlight::Border,
+            border_width: 1.0,
+            active_color: None,
+            radius: CornerRadius::S,
+            inactive_opacity: 0.6,
+        }
+    }
+
+    fn inputs<'a>(toplevels: &'a [AppToplevel], style: WorkspaceStyle) -> WorkspaceWidget<'a> {
+        WorkspaceWidget {
+            label: "1".to_string(),
+            toplevels,
+            icon: None,
+            is_active: false,
+            is_drop_target: false,
+            style,
+        }
+    }
+
+    #[test]
+    fn window_count_is_shown_only_if_enabled_and_there_are_windows() {
+        let handles = Handles::new().expect("mock handles");
+        let workspace = handles.workspace("1", vec![0]);
+        let toplevels = [
+            handles.toplevel(&workspace, "a"),
+            handles.toplevel(&workspace, "b"),
+        ];
+        let counted = WorkspaceStyle {
+            show_window_count: true,
+            ..style()
+        };
+        assert_eq!(inputs(&toplevels, counted).window_count(), Some(2));
+        assert_eq!(inputs(&[], counted).window_count(), None);
+        assert_eq!(inputs(&toplevels, style()).window_count(), None);
+    }
+
+    #[test]
+    fn empty_workspaces_without_name_or_icon_show_a_marker() {
+        let handles = Handles::new().expect("mock handles");
+        let workspace = handles.workspace("1", vec![0]);
+        let toplevels = [handles.toplevel(&workspace, "a")];
+        let unnamed = WorkspaceStyle {
+            show_name: false,
+            ..style()
+        };
+        assert!(inputs(&[], unnamed).needs_marker());
+        assert!(!inputs(&[], style()).needs_marker());
+        assert!(!inputs(&toplevels, unnamed).needs_marker());
+        let with_icon = WorkspaceWidget {
+            icon: Some(widget::icon::from_name("folder-symbolic").icon()),
+            ..inputs(&[], unnamed)
+        };
+        assert!(!with_icon.needs_marker());
+    }
+}